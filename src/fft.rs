@@ -0,0 +1,150 @@
+use std::{
+    f64::consts::TAU,
+    ops::{Add, Mul, Sub},
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+
+    /// `e^(i angle)`
+    pub fn from_angle(angle: f64) -> Self {
+        Self::new(angle.cos(), angle.sin())
+    }
+
+    pub fn conj(self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+
+    pub fn abs(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+}
+
+impl Add for Complex {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+impl Mul<f64> for Complex {
+    type Output = Self;
+
+    fn mul(self, rhs: f64) -> Self {
+        Self::new(self.re * rhs, self.im * rhs)
+    }
+}
+
+/// In place radix 2 FFT, `data.len()` has to be a power of 2.
+pub fn fft(data: &mut [Complex]) {
+    transform(data, false);
+}
+
+/// Inverse of [`fft`], including the `1 / n` scaling.
+pub fn ifft(data: &mut [Complex]) {
+    transform(data, true);
+
+    let scale = 1. / data.len() as f64;
+    for v in data {
+        *v = *v * scale;
+    }
+}
+
+fn transform(data: &mut [Complex], inverse: bool) {
+    let n = data.len();
+    assert!(n.is_power_of_two(), "FFT size {n} isn't a power of 2");
+
+    // bit reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1. } else { -1. };
+
+    let mut len = 2;
+    while len <= n {
+        let w_len = Complex::from_angle(sign * TAU / len as f64);
+
+        for chunk in data.chunks_exact_mut(len) {
+            let (a, b) = chunk.split_at_mut(len / 2);
+            let mut w = Complex::new(1., 0.);
+
+            for (a, b) in a.iter_mut().zip(b) {
+                let t = *b * w;
+                *b = *a - t;
+                *a = *a + t;
+                w = w * w_len;
+            }
+        }
+
+        len <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_dft_and_inverts() {
+        let input: Vec<_> = (0..16)
+            .map(|i| Complex::new((i as f64 * 0.7).sin(), (i as f64 * 1.3).cos()))
+            .collect();
+
+        let mut data = input.clone();
+        fft(&mut data);
+
+        for (k, v) in data.iter().enumerate() {
+            let dft = input
+                .iter()
+                .enumerate()
+                .map(|(i, x)| *x * Complex::from_angle(-TAU * (i * k) as f64 / 16.))
+                .fold(Complex::default(), |a, b| a + b);
+
+            assert!((*v - dft).abs() < 1e-9);
+        }
+
+        ifft(&mut data);
+        for (a, b) in data.iter().zip(&input) {
+            assert!((*a - *b).abs() < 1e-12);
+        }
+    }
+}
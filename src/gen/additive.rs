@@ -0,0 +1,112 @@
+use std::fmt::Display;
+
+use crate::parse::{Expression, ExpressionError};
+
+use super::{harmonic, GenInfo, Oscillator, PerChannel};
+
+#[derive(Debug)]
+pub struct AdditiveSource {
+    pub(crate) freq: Expression,
+    pub(crate) phase: Expression,
+
+    pub(crate) amplitudes: Vec<Expression>,
+    /// In radians, missing ones are 0.
+    pub(crate) phases: Vec<Expression>,
+    /// In cents, missing ones are 0.
+    pub(crate) detune: Vec<Expression>,
+
+    pub(crate) osc: PerChannel<Vec<Oscillator>>,
+}
+
+impl AdditiveSource {
+    pub fn new(freq: Expression, phase: Expression, amplitudes: Vec<Expression>) -> Self {
+        Self {
+            freq,
+            phase,
+            amplitudes,
+            phases: vec![],
+            detune: vec![],
+            osc: PerChannel::default(),
+        }
+    }
+
+    pub fn gen(&mut self, gi: GenInfo) -> Result<f64, ExpressionError> {
+        let freq = self.freq.evaluate(Some(gi))?;
+        let phase = self.phase.evaluate(Some(gi))?;
+
+        let osc = self.osc.get(gi.channel);
+        osc.resize_with(self.amplitudes.len(), Oscillator::default);
+
+        let mut v = 0.;
+        for (i, (amp, osc)) in self.amplitudes.iter().zip(osc).enumerate() {
+            let nth = i + 1;
+
+            let cents = match self.detune.get(i) {
+                Some(d) => d.evaluate(Some(gi))?,
+                None => 0.,
+            };
+            let partial_phase = match self.phases.get(i) {
+                Some(p) => p.evaluate(Some(gi))?,
+                None => 0.,
+            };
+
+            // every partial runs its own (detuned) fundamental, harmonic() multiplies it up
+            let cycles = osc.advance(gi.secs(), freq * f64::powf(2., cents / 1200.));
+
+            // partials above nyquist would only alias
+            if osc.step().abs() * nth as f64 >= 0.5 {
+                continue;
+            }
+
+            let phase = phase * nth as f64 + partial_phase;
+            v += amp.evaluate(Some(gi))? * harmonic(nth, cycles, 1., phase);
+        }
+
+        Ok(v)
+    }
+}
+
+impl Display for AdditiveSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let list = |l: &[Expression]| {
+            l.iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        write!(
+            f,
+            "{} Hz (phase: {}) additive [{}]",
+            self.freq,
+            self.phase,
+            list(&self.amplitudes)
+        )?;
+
+        if !self.phases.is_empty() {
+            write!(f, " (phases: [{}])", list(&self.phases))?;
+        }
+        if !self.detune.is_empty() {
+            write!(f, " (detune: [{}])", list(&self.detune))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gen::tests::{power, render};
+
+    #[test]
+    fn partials_above_nyquist_are_skipped() {
+        // the 8th partial at 24080 Hz would fold back to 20020 Hz
+        let x = render(
+            "\"t\" 0.1s on 1 additive(3010 hz, 0 rad, [1, 1, 1, 1, 1, 1, 1, 1], :) on * @ 1 {}",
+        );
+
+        let seventh = power(&x, 21070.);
+        let alias = power(&x, 20020.);
+        assert!(alias < seventh * 1e-6, "{alias} vs {seventh}");
+    }
+}
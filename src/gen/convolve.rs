@@ -0,0 +1,222 @@
+use std::{collections::VecDeque, fmt::Display};
+
+use crate::{
+    fft::{self, Complex},
+    parse::{tokenizer::Number, Expression, ExpressionError},
+    wav::reader::Wave,
+};
+
+use super::{dry_wet, sample::interpolate, GenInfo, PerChannel};
+
+/// Taps convolved directly, and the size of every FFT partition after them.
+const BLOCK: usize = 256;
+
+#[derive(Debug)]
+struct Kernel {
+    /// The first `BLOCK` taps, so there's no latency.
+    head: Vec<f64>,
+    /// Spectra of the partitions, zero padded to `2 * BLOCK`.
+    partitions: Vec<Vec<Complex>>,
+}
+
+impl Kernel {
+    fn new(ir: &[f64]) -> Self {
+        let head = ir.iter().copied().take(BLOCK).collect();
+
+        let partitions = ir
+            .get(BLOCK..)
+            .unwrap_or_default()
+            .chunks(BLOCK)
+            .map(|chunk| {
+                let mut spectrum = vec![Complex::default(); 2 * BLOCK];
+                for (s, v) in spectrum.iter_mut().zip(chunk) {
+                    *s = Complex::new(*v, 0.);
+                }
+                fft::fft(&mut spectrum);
+                spectrum
+            })
+            .collect();
+
+        Self { head, partitions }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ConvolutionState {
+    /// Ring buffer of the inputs for the head.
+    history: Vec<f64>,
+    /// The previous input block followed by the current one.
+    input: Vec<f64>,
+    pos: usize,
+
+    /// Newest first.
+    spectra: VecDeque<Vec<Complex>>,
+    /// What the partitions add to the current block.
+    tail: Vec<f64>,
+}
+
+/// Uniformly partitioned overlap-save, the head is convolved directly.
+#[derive(Debug)]
+pub struct Convolution {
+    pub(crate) path: String,
+    pub(crate) ir: Wave,
+    pub(crate) mix: Expression,
+
+    /// Made once the sample rate is known.
+    kernels: Vec<Kernel>,
+    state: PerChannel<ConvolutionState>,
+}
+
+impl Convolution {
+    pub fn new(path: String, ir: Wave) -> Self {
+        Self {
+            path,
+            ir,
+            mix: Expression::Lit(Number::Real(0.3)),
+            kernels: vec![],
+            state: PerChannel::default(),
+        }
+    }
+
+    /// Length of the impulse response in seconds.
+    pub fn tail(&self) -> f64 {
+        self.ir.frames() as f64 / self.ir.desc.samplerate() as f64
+    }
+
+    /// Resamples the impulse response to `samplerate` and normalizes it to unity power gain.
+    fn make_kernels(&mut self, samplerate: usize) {
+        let ratio = self.ir.desc.samplerate() as f64 / samplerate as f64;
+        let len = (self.ir.frames() as f64 / ratio).ceil() as usize;
+
+        let channels: Vec<Vec<f64>> = (0..self.ir.desc.channels() as usize)
+            .map(|c| {
+                (0..len)
+                    .map(|i| interpolate(&self.ir, i as f64 * ratio, c))
+                    .collect()
+            })
+            .collect();
+
+        let energy = channels
+            .iter()
+            .map(|c| c.iter().map(|v| v * v).sum::<f64>())
+            .fold(0., f64::max);
+        let gain = if energy > 0. {
+            energy.sqrt().recip()
+        } else {
+            0.
+        };
+
+        self.kernels = channels
+            .iter()
+            .map(|c| Kernel::new(&c.iter().map(|v| v * gain).collect::<Vec<_>>()))
+            .collect();
+    }
+
+    pub fn apply(&mut self, v: f64, gi: GenInfo) -> Result<f64, ExpressionError> {
+        let mix = self.mix.evaluate(Some(gi))?;
+
+        if self.kernels.is_empty() {
+            self.make_kernels(gi.samplerate);
+        }
+        let Some(kernel) = self.kernels.get(gi.channel % self.kernels.len().max(1)) else {
+            return Ok(dry_wet(v, 0., mix));
+        };
+
+        let state = self.state.get(gi.channel);
+        if state.history.is_empty() {
+            state.history = vec![0.; BLOCK];
+            state.input = vec![0.; 2 * BLOCK];
+            state.tail = vec![0.; BLOCK];
+        }
+
+        let pos = state.pos;
+        state.history[pos] = v;
+        state.input[BLOCK + pos] = v;
+
+        let mut wet = state.tail[pos];
+        for (i, h) in kernel.head.iter().enumerate() {
+            wet += h * state.history[(pos + BLOCK - i) % BLOCK];
+        }
+
+        state.pos += 1;
+        if state.pos == BLOCK {
+            state.pos = 0;
+
+            if !kernel.partitions.is_empty() {
+                let mut spectrum: Vec<_> =
+                    state.input.iter().map(|v| Complex::new(*v, 0.)).collect();
+                fft::fft(&mut spectrum);
+
+                state.spectra.push_front(spectrum);
+                state.spectra.truncate(kernel.partitions.len());
+
+                let mut sum = vec![Complex::default(); 2 * BLOCK];
+                for (x, h) in state.spectra.iter().zip(&kernel.partitions) {
+                    for ((s, x), h) in sum.iter_mut().zip(x).zip(h) {
+                        *s = *s + *x * *h;
+                    }
+                }
+                fft::ifft(&mut sum);
+
+                // overlap-save, only the second half is free of wrap around
+                for (t, s) in state.tail.iter_mut().zip(&sum[BLOCK..]) {
+                    *t = s.re;
+                }
+            }
+
+            state.input.copy_within(BLOCK.., 0);
+        }
+
+        Ok(dry_wet(v, wet, mix))
+    }
+}
+
+impl Display for Convolution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "convolve \"{}\" (mix: {})", self.path, self.mix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wav::{self, reader, SampleFormat};
+
+    #[test]
+    fn matches_direct_convolution() {
+        const RATE: usize = 44100;
+
+        let mut rng = crate::gen::noise::Rng::new(1);
+        let ir: Vec<f64> = (0..1000)
+            .map(|i| rng.next_bipolar() * f64::exp(-(i as f64) / 200.))
+            .collect();
+        let input: Vec<f64> = (0..3000).map(|_| rng.next_bipolar()).collect();
+
+        let data: Vec<u8> = ir.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let mut file = vec![];
+        wav::write_to_wav(1, RATE, SampleFormat::Float(64), &data, &mut file).unwrap();
+
+        let mut convolution = Convolution::new("ir".to_string(), reader::read(&file[..]).unwrap());
+        convolution.mix = Expression::Lit(Number::Integer(1));
+
+        let gain = ir.iter().map(|v| v * v).sum::<f64>().sqrt().recip();
+        for (n, x) in input.iter().enumerate() {
+            let gi = GenInfo {
+                channel: 0,
+                channels: 1,
+                samplerate: RATE,
+                t: 0.,
+                len_s: 1.,
+                song_t: 0.,
+                song_secs: 0.,
+                input: None,
+            };
+            let y = convolution.apply(*x, gi).unwrap();
+
+            let direct: f64 = (0..=n.min(ir.len() - 1))
+                .map(|i| ir[i] * gain * input[n - i])
+                .sum();
+            assert!((y - direct).abs() < 1e-9, "sample {n}: {y} vs {direct}");
+        }
+    }
+}
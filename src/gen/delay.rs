@@ -0,0 +1,226 @@
+use std::fmt::Display;
+
+use crate::parse::{tokenizer::Number, Expression, ExpressionError};
+
+use super::{dry_wet, GenInfo};
+
+/// Feedback that isn't a constant is assumed to be this high when working out the tail.
+const VARIABLE_FEEDBACK: f64 = 0.9;
+
+/// Trips around a feedback loop until it has faded by 60 dB, at least 1.
+pub fn echoes(feedback: &Expression) -> f64 {
+    let feedback = match feedback.evaluate(None) {
+        Ok(f) => f.abs().min(0.99),
+        Err(_) => VARIABLE_FEEDBACK,
+    };
+
+    if feedback > 0. {
+        f64::max(f64::ln(0.001) / feedback.ln(), 1.)
+    } else {
+        1.
+    }
+}
+
+/// A circular buffer of past samples.
+#[derive(Debug, Default, Clone)]
+pub struct DelayLine {
+    buffer: Vec<f64>,
+    pos: usize,
+}
+
+impl DelayLine {
+    pub fn new(len: usize) -> Self {
+        Self {
+            buffer: vec![0.; len.max(1)],
+            pos: 0,
+        }
+    }
+
+    /// The sample written as many samples ago as the line is long.
+    pub fn read(&self) -> f64 {
+        self.buffer[self.pos]
+    }
+
+    /// The sample written `samples` ago (0 is the last one), linearly interpolated.
+    pub fn tap(&self, samples: f64) -> f64 {
+        let len = self.buffer.len();
+        let samples = samples.clamp(0., (len - 1) as f64);
+
+        let whole = samples.floor();
+        let newer = self.buffer[(self.pos + len - 1 - whole as usize) % len];
+        let older = self.buffer[(self.pos + 2 * len - 2 - whole as usize) % len];
+
+        newer + (older - newer) * (samples - whole)
+    }
+
+    pub fn write(&mut self, v: f64) {
+        self.buffer[self.pos] = v;
+        self.pos = (self.pos + 1) % self.buffer.len();
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DelayState {
+    lines: Vec<DelayLine>,
+    /// Last output of every line, for ping-pong feedback.
+    wet: Vec<f64>,
+    /// Input gathered from all channels for the first line (ping-pong).
+    input: f64,
+}
+
+/// Echoes with feedback, optionally bouncing between the channels.
+#[derive(Debug)]
+pub struct Delay {
+    /// In seconds.
+    pub(crate) time: f64,
+    pub(crate) feedback: Expression,
+    pub(crate) mix: Expression,
+    pub(crate) ping_pong: bool,
+
+    pub(crate) state: DelayState,
+}
+
+impl Delay {
+    pub fn new(time: f64) -> Self {
+        Self {
+            time,
+            feedback: Expression::Lit(Number::Real(0.4)),
+            mix: Expression::Lit(Number::Real(0.3)),
+            ping_pong: false,
+            state: DelayState::default(),
+        }
+    }
+
+    /// Seconds until the echoes have faded by 60 dB.
+    pub fn tail(&self) -> f64 {
+        // ping-pong spreads every echo over all the channels
+        self.time * echoes(&self.feedback) * if self.ping_pong { 2. } else { 1. }
+    }
+
+    pub fn apply(&mut self, v: f64, gi: GenInfo) -> Result<f64, ExpressionError> {
+        let feedback = self.feedback.evaluate(Some(gi))?;
+        let mix = self.mix.evaluate(Some(gi))?;
+
+        let channels = gi.channels.max(gi.channel + 1);
+        let state = &mut self.state;
+        if state.lines.len() < channels {
+            let len = (self.time * gi.samplerate as f64).round() as usize;
+
+            // the first line only gets its input a frame late when ping-ponging
+            state.lines = (0..channels)
+                .map(|c| match c {
+                    0 if self.ping_pong => DelayLine::new(len.saturating_sub(1)),
+                    _ => DelayLine::new(len),
+                })
+                .collect();
+            state.wet = vec![0.; channels];
+        }
+
+        let c = gi.channel;
+        let wet = state.lines[c].read();
+
+        let input = if self.ping_pong {
+            let previous = state.wet[(c + channels - 1) % channels];
+            let input = match c {
+                0 => std::mem::take(&mut state.input),
+                _ => 0.,
+            };
+            state.input += v / channels as f64;
+
+            input + feedback * previous
+        } else {
+            v + feedback * wet
+        };
+
+        state.lines[c].write(input);
+        state.wet[c] = wet;
+
+        Ok(dry_wet(v, wet, mix))
+    }
+}
+
+impl Display for Delay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "delay {}s (feedback: {}, mix: {}",
+            self.time, self.feedback, self.mix
+        )?;
+        if self.ping_pong {
+            write!(f, ", ping-pong")?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        gen::tests::{render_channels, RATE},
+        parse::{get_song, ParserError},
+    };
+
+    /// Non-silent samples of every channel, as (frame, channel, value).
+    fn echoes(delay: &str) -> Vec<(usize, usize, f64)> {
+        let x = render_channels(&format!(
+            "\"t\" 0.5s on 2 impulse(0, :0.1s) on * @ 1 {{ delay(time 100ms, feedback 0.5, mix 1, {delay}) : }}"
+        ));
+
+        let mut echoes = vec![];
+        for i in 0..x[0].len() {
+            for (c, x) in x.iter().enumerate() {
+                if x[i].abs() > 1e-3 {
+                    echoes.push((i, c, x[i]));
+                }
+            }
+        }
+        echoes
+    }
+
+    fn assert_echoes(delay: &str, expected: &[(usize, usize, f64)]) {
+        let echoes = echoes(delay);
+        assert_eq!(echoes.len(), expected.len(), "{delay}: {echoes:?}");
+
+        for (echo, expected) in echoes.iter().zip(expected) {
+            assert_eq!((echo.0, echo.1), (expected.0, expected.1), "{delay}");
+            assert!((echo.2 - expected.2).abs() < 1e-9, "{delay}: {echo:?}");
+        }
+    }
+
+    #[test]
+    fn echoes_come_every_delay_time_and_fade_by_the_feedback() {
+        let t = RATE / 10;
+
+        assert_echoes(
+            "",
+            &[
+                (t, 0, 1.),
+                (t, 1, 1.),
+                (2 * t, 0, 0.5),
+                (2 * t, 1, 0.5),
+                (3 * t, 0, 0.25),
+                (3 * t, 1, 0.25),
+                (4 * t, 0, 0.125),
+                (4 * t, 1, 0.125),
+            ],
+        );
+        assert_echoes(
+            "pingpong",
+            &[
+                (t, 0, 1.),
+                (2 * t, 1, 0.5),
+                (3 * t, 0, 0.25),
+                (4 * t, 1, 0.125),
+            ],
+        );
+    }
+
+    #[test]
+    fn ping_pong_needs_every_channel() {
+        let src = "\"t\" 1s on 2 impulse(0, :) on 0 @ 1 { delay(time 100ms, pingpong) : }";
+        assert!(matches!(
+            get_song("test", src),
+            Err(ParserError::NotAllChannels)
+        ));
+    }
+}
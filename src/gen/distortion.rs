@@ -0,0 +1,366 @@
+use std::fmt::Display;
+
+use crate::parse::{tokenizer::Number, Expression, ExpressionError};
+
+use super::{
+    dry_wet,
+    dynamics::db_to_gain,
+    filter::{Biquad, Coefficients, FilterKind},
+    GenInfo, PerChannel,
+};
+
+/// Q of the sections of an 8th order Butterworth lowpass.
+const BUTTERWORTH_8: [f64; 4] = [0.5098, 0.6013, 0.9000, 2.5629];
+/// Cutoff of the oversampling filters, relative to the original sample rate.
+const OVERSAMPLING_CUTOFF: f64 = 0.45;
+pub const MAX_OVERSAMPLING: usize = 16;
+
+/// How far the asymmetric curve is biased, so the halves saturate differently.
+const ASYMMETRY: f64 = 0.5;
+
+#[derive(Debug, Default)]
+pub struct OversamplingState {
+    up: [Biquad; 4],
+    down: [Biquad; 4],
+}
+
+impl OversamplingState {
+    fn filters(factor: usize, samplerate: usize) -> [Coefficients; 4] {
+        let rate = (samplerate * factor) as f64;
+        let cutoff = samplerate as f64 * OVERSAMPLING_CUTOFF;
+
+        BUTTERWORTH_8.map(|q| Coefficients::new(FilterKind::Lowpass, rate, cutoff, q, 0.))
+    }
+
+    fn process<F>(
+        &mut self,
+        filters: &[Coefficients; 4],
+        factor: usize,
+        x: f64,
+        mut f: F,
+    ) -> Result<f64, ExpressionError>
+    where
+        F: FnMut(f64) -> Result<f64, ExpressionError>,
+    {
+        if factor <= 1 {
+            return f(x);
+        }
+
+        let mut out = 0.;
+        for i in 0..factor {
+            // zero stuffing, the gain makes up for the zeros
+            let mut v = if i == 0 { x * factor as f64 } else { 0. };
+            for (b, c) in self.up.iter_mut().zip(filters) {
+                v = b.process(c, v);
+            }
+
+            let mut v = f(v)?;
+            for (b, c) in self.down.iter_mut().zip(filters) {
+                v = b.process(c, v);
+            }
+
+            out = v;
+        }
+
+        Ok(out)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Oversampler {
+    /// Made once the sample rate is known.
+    filters: Option<(usize, [Coefficients; 4])>,
+    state: PerChannel<OversamplingState>,
+}
+
+impl Oversampler {
+    fn process<F>(
+        &mut self,
+        factor: usize,
+        x: f64,
+        gi: GenInfo,
+        f: F,
+    ) -> Result<f64, ExpressionError>
+    where
+        F: FnMut(f64) -> Result<f64, ExpressionError>,
+    {
+        let filters = match self.filters {
+            Some((rate, filters)) if rate == gi.samplerate => filters,
+            _ => {
+                let filters = OversamplingState::filters(factor, gi.samplerate);
+                self.filters = Some((gi.samplerate, filters));
+                filters
+            }
+        };
+
+        self.state.get(gi.channel).process(&filters, factor, x, f)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Curve {
+    Tanh,
+    Hard,
+    /// Reflects off ±1 instead of clipping.
+    Fold,
+    /// Saturates one half earlier than the other, for even harmonics.
+    Asymmetric,
+}
+
+impl Curve {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "tanh" | "soft" => Self::Tanh,
+            "hard" | "clip" => Self::Hard,
+            "fold" => Self::Fold,
+            "asym" | "asymmetric" => Self::Asymmetric,
+
+            _ => return None,
+        })
+    }
+
+    pub fn shape(self, x: f64) -> f64 {
+        match self {
+            Self::Tanh => x.tanh(),
+            Self::Hard => x.clamp(-1., 1.),
+            Self::Fold => {
+                let x = (x + 1.).rem_euclid(4.);
+                if x < 2. {
+                    x - 1.
+                } else {
+                    3. - x
+                }
+            }
+            Self::Asymmetric => f64::tanh(x + ASYMMETRY) - ASYMMETRY.tanh(),
+        }
+    }
+}
+
+impl Display for Curve {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Curve::Tanh => write!(f, "tanh"),
+            Curve::Hard => write!(f, "hard"),
+            Curve::Fold => write!(f, "fold"),
+            Curve::Asymmetric => write!(f, "asymmetric"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Drive {
+    /// In dB.
+    pub(crate) amount: Expression,
+    pub(crate) curve: Curve,
+    pub(crate) oversampling: usize,
+    pub(crate) mix: Expression,
+
+    state: Oversampler,
+}
+
+impl Drive {
+    pub fn new(amount: Expression) -> Self {
+        Self {
+            amount,
+            curve: Curve::Tanh,
+            oversampling: 1,
+            mix: Expression::Lit(Number::Integer(1)),
+            state: Oversampler::default(),
+        }
+    }
+
+    pub fn apply(&mut self, v: f64, gi: GenInfo) -> Result<f64, ExpressionError> {
+        let gain = db_to_gain(self.amount.evaluate(Some(gi))?);
+        let mix = self.mix.evaluate(Some(gi))?;
+
+        let curve = self.curve;
+        let wet = self
+            .state
+            .process(self.oversampling, v, gi, |x| Ok(curve.shape(x * gain)))?;
+
+        Ok(dry_wet(v, wet, mix))
+    }
+}
+
+impl Display for Drive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "drive {} dB (curve: {}, oversampling: {}x, mix: {})",
+            self.amount, self.curve, self.oversampling, self.mix
+        )
+    }
+}
+
+/// Runs every sample through a transfer function of `x`.
+#[derive(Debug)]
+pub struct Waveshaper {
+    pub(crate) transfer: Expression,
+    pub(crate) oversampling: usize,
+    pub(crate) mix: Expression,
+
+    state: Oversampler,
+}
+
+impl Waveshaper {
+    pub fn new(transfer: Expression) -> Self {
+        Self {
+            transfer,
+            oversampling: 1,
+            mix: Expression::Lit(Number::Integer(1)),
+            state: Oversampler::default(),
+        }
+    }
+
+    pub fn apply(&mut self, v: f64, gi: GenInfo) -> Result<f64, ExpressionError> {
+        let mix = self.mix.evaluate(Some(gi))?;
+
+        let transfer = &self.transfer;
+        let wet = self.state.process(self.oversampling, v, gi, |x| {
+            transfer.evaluate(Some(GenInfo {
+                input: Some(x),
+                ..gi
+            }))
+        })?;
+
+        Ok(dry_wet(v, wet, mix))
+    }
+}
+
+impl Display for Waveshaper {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "waveshape {} (oversampling: {}x, mix: {})",
+            self.transfer, self.oversampling, self.mix
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct BitcrushState {
+    count: f64,
+    held: f64,
+}
+
+#[derive(Debug)]
+pub struct Bitcrush {
+    pub(crate) bits: Expression,
+    /// Every sample is held for this many.
+    pub(crate) rate_reduction: Expression,
+    pub(crate) mix: Expression,
+
+    state: PerChannel<BitcrushState>,
+}
+
+impl Bitcrush {
+    pub fn new(bits: Expression, rate_reduction: Expression) -> Self {
+        Self {
+            bits,
+            rate_reduction,
+            mix: Expression::Lit(Number::Integer(1)),
+            state: PerChannel::default(),
+        }
+    }
+
+    pub fn apply(&mut self, v: f64, gi: GenInfo) -> Result<f64, ExpressionError> {
+        // steps either side of 0, like a signed integer there's one less above it
+        let steps = f64::powf(2., self.bits.evaluate(Some(gi))?.max(1.) - 1.);
+        let reduction = self.rate_reduction.evaluate(Some(gi))?.max(1.);
+        let mix = self.mix.evaluate(Some(gi))?;
+
+        let state = self.state.get(gi.channel);
+        if state.count <= 0. {
+            state.held = (v * steps).round().clamp(-steps, steps - 1.) / steps;
+            state.count += reduction;
+        }
+        state.count -= 1.;
+
+        Ok(dry_wet(v, state.held, mix))
+    }
+}
+
+impl Display for Bitcrush {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "bitcrush {} bits (rate reduction: {}, mix: {})",
+            self.bits, self.rate_reduction, self.mix
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        gen::tests::{alias_ratio, render},
+        parse::{get_song, ParserError},
+    };
+
+    fn sine(freq: usize, effects: &str) -> Vec<f64> {
+        render(&format!(
+            "\"t\" 0.1s on 1 sine({freq} hz, 0 rad, :) on * @ 1 {{ {effects} }}"
+        ))
+    }
+
+    #[test]
+    fn waveshaper_runs_the_expression() {
+        let dry = sine(200, "");
+
+        for (shape, f) in [
+            (
+                "tanh(x * 4)",
+                (|x: f64| f64::tanh(x * 4.)) as fn(f64) -> f64,
+            ),
+            ("fold(x * 3)", |x| super::Curve::Fold.shape(x * 3.)),
+            ("x * x * x", |x| x * x * x),
+            ("-x", |x| -x),
+        ] {
+            let wet = sine(200, &format!("waveshape({shape}) :"));
+
+            for (d, w) in dry.iter().zip(&wet) {
+                assert!((f(*d) - w).abs() < 1e-9, "{shape}: {d} became {w}");
+            }
+        }
+    }
+
+    #[test]
+    fn broken_effects_report_their_own_error() {
+        let src = "\"t\" 1s on 1 sine(200 hz, 0 rad, :) on * @ 1 { drive(12, curve nope) : }";
+        assert!(matches!(
+            get_song("test", src),
+            Err(ParserError::InvalidOption(o)) if o == "nope"
+        ));
+    }
+
+    #[test]
+    fn oversampling_cuts_aliasing() {
+        // 10 Hz bins, so every partial and alias lands on a bin
+        const F0: usize = 3010;
+
+        let plain = alias_ratio(&sine(F0, "drive(24, curve hard) :"), F0);
+        let oversampled = alias_ratio(&sine(F0, "drive(24, curve hard, oversample 4) :"), F0);
+
+        let (plain, oversampled) = (10. * plain.log10(), 10. * oversampled.log10());
+        assert!(
+            oversampled < plain - 20.,
+            "{oversampled:.1} dB oversampled, {plain:.1} dB without"
+        );
+    }
+
+    #[test]
+    fn bitcrush_quantizes_and_holds() {
+        for bits in [1, 2, 3, 4] {
+            let x = sine(200, &format!("bitcrush({bits}, 4) :"));
+
+            for hold in x.chunks(4) {
+                assert!(hold.iter().all(|v| *v == hold[0]), "{hold:?}");
+            }
+
+            let mut levels = x;
+            levels.sort_by(f64::total_cmp);
+            levels.dedup();
+            assert_eq!(levels.len(), 1 << bits, "{bits} bits: {levels:?}");
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use std::{collections::VecDeque, fmt::Display};
+
+use crate::parse::{tokenizer::Number, Expression, ExpressionError};
+
+use super::{delay::DelayLine, GenInfo, PerChannel};
+
+/// Gain reduction never goes further than this (dB), so gates don't produce -inf.
+const FLOOR_DB: f64 = -120.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DynamicsKind {
+    /// Turns down what's above the threshold.
+    Compressor,
+    /// A compressor that looks ahead so nothing gets past the threshold.
+    Limiter,
+    /// Turns down what's below the threshold.
+    Expander,
+    /// An expander with a very high ratio.
+    Gate,
+}
+
+impl DynamicsKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "compressor" | "compress" => Self::Compressor,
+            "limiter" | "limit" => Self::Limiter,
+            "expander" | "expand" => Self::Expander,
+            "gate" => Self::Gate,
+
+            _ => return None,
+        })
+    }
+
+    fn downward(self) -> bool {
+        matches!(self, Self::Compressor | Self::Limiter)
+    }
+}
+
+impl Display for DynamicsKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DynamicsKind::Compressor => write!(f, "compressor"),
+            DynamicsKind::Limiter => write!(f, "limiter"),
+            DynamicsKind::Expander => write!(f, "expander"),
+            DynamicsKind::Gate => write!(f, "gate"),
+        }
+    }
+}
+
+pub fn db_to_gain(db: f64) -> f64 {
+    f64::powf(10., db / 20.)
+}
+
+pub fn gain_to_db(gain: f64) -> f64 {
+    20. * gain.abs().max(1e-12).log10()
+}
+
+/// Coefficient of a one pole smoother that gets about 63% of the way in `secs`.
+fn smoothing(secs: f64, samplerate: usize) -> f64 {
+    if secs > 0. {
+        f64::exp(-1. / (secs * samplerate as f64))
+    } else {
+        0.
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DynamicsState {
+    /// Peak level of the input, following it at the attack and release times.
+    level: f64,
+    /// Smoothed gain of the limiter in dB.
+    gain: f64,
+
+    // look-ahead
+    signal: Option<DelayLine>,
+    /// Indices and gains of a running minimum over the look-ahead.
+    minimum: VecDeque<(u64, f64)>,
+    /// The last look-ahead's worth of held minimums, and their sum.
+    average: VecDeque<f64>,
+    sum: f64,
+    n: u64,
+}
+
+/// Compressor, limiter, expander and gate, with soft knees.
+#[derive(Debug)]
+pub struct Dynamics {
+    pub(crate) kind: DynamicsKind,
+    /// In dB.
+    pub(crate) threshold: Expression,
+    pub(crate) ratio: Expression,
+    /// In seconds, the limiter attacks over its look-ahead instead.
+    pub(crate) attack: f64,
+    pub(crate) release: f64,
+    /// Width of the knee in dB.
+    pub(crate) knee: Expression,
+    /// In dB.
+    pub(crate) makeup: Expression,
+    /// In seconds, only used by the limiter.
+    pub(crate) lookahead: f64,
+
+    pub(crate) state: PerChannel<DynamicsState>,
+}
+
+impl Dynamics {
+    pub fn new(kind: DynamicsKind) -> Self {
+        let (threshold, ratio) = match kind {
+            DynamicsKind::Compressor => (-20., 4.),
+            DynamicsKind::Limiter => (-1., f64::INFINITY),
+            DynamicsKind::Expander => (-40., 2.),
+            DynamicsKind::Gate => (-50., 100.),
+        };
+
+        Self {
+            kind,
+            threshold: Expression::Lit(Number::Real(threshold)),
+            ratio: Expression::Lit(Number::Real(ratio)),
+            attack: 0.01,
+            release: 0.1,
+            knee: Expression::zero(),
+            makeup: Expression::zero(),
+            lookahead: 0.005,
+            state: PerChannel::default(),
+        }
+    }
+
+    /// The limiter delays the signal by its look-ahead.
+    pub fn tail(&self) -> f64 {
+        match self.kind {
+            DynamicsKind::Limiter => self.lookahead,
+            _ => 0.,
+        }
+    }
+
+    /// Static curve: gain in dB for a level of `x` dB.
+    fn gain_for(&self, x: f64, threshold: f64, ratio: f64, knee: f64) -> f64 {
+        let over = x - threshold;
+        let ratio = ratio.max(1.);
+
+        let gain = if self.kind.downward() {
+            let slope = 1. / ratio - 1.;
+            if 2. * over <= -knee {
+                0.
+            } else if 2. * over.abs() < knee {
+                slope * (over + knee / 2.).powi(2) / (2. * knee)
+            } else {
+                slope * over
+            }
+        } else {
+            let slope = ratio - 1.;
+            if 2. * over >= knee {
+                0.
+            } else if 2. * over.abs() < knee {
+                -slope * (over - knee / 2.).powi(2) / (2. * knee)
+            } else {
+                slope * over
+            }
+        };
+
+        gain.max(FLOOR_DB)
+    }
+
+    pub fn apply(&mut self, v: f64, gi: GenInfo) -> Result<f64, ExpressionError> {
+        let threshold = self.threshold.evaluate(Some(gi))?;
+        let ratio = self.ratio.evaluate(Some(gi))?;
+        let knee = self.knee.evaluate(Some(gi))?.max(0.);
+        let makeup = db_to_gain(self.makeup.evaluate(Some(gi))?);
+
+        let attack = smoothing(self.attack, gi.samplerate);
+        let release = smoothing(self.release, gi.samplerate);
+        let lookahead = (self.lookahead * gi.samplerate as f64).round().max(1.) as usize;
+
+        if self.kind != DynamicsKind::Limiter {
+            // the level has to ride over the zero crossings, or the gain follows the waveform
+            let state = self.state.get(gi.channel);
+            let x = v.abs();
+            let coefficient = if x > state.level { attack } else { release };
+            state.level = x + (state.level - x) * coefficient;
+
+            let level = gain_to_db(state.level);
+            let gain = self.gain_for(level, threshold, ratio, knee);
+            return Ok(v * db_to_gain(gain) * makeup);
+        }
+
+        let target = self.gain_for(gain_to_db(v), threshold, ratio, knee);
+        let state = self.state.get(gi.channel);
+
+        // the limiter reacts instantly and only smooths the release
+        state.gain = if target < state.gain {
+            target
+        } else {
+            target + (state.gain - target) * release
+        };
+
+        // the minimum over the look-ahead, averaged over the look-ahead again, is down to the
+        // peak's gain by the time the delayed peak comes out
+        let n = state.n;
+        state.n += 1;
+
+        while state.minimum.back().is_some_and(|(_, g)| *g >= state.gain) {
+            state.minimum.pop_back();
+        }
+        state.minimum.push_back((n, state.gain));
+        while state
+            .minimum
+            .front()
+            .is_some_and(|(i, _)| i + (lookahead as u64) <= n)
+        {
+            state.minimum.pop_front();
+        }
+        let held = db_to_gain(state.minimum.front().map_or(0., |(_, g)| *g));
+
+        state.average.push_back(held);
+        state.sum += held;
+        if state.average.len() > lookahead {
+            state.sum -= state.average.pop_front().unwrap_or(0.);
+        }
+        let gain = state.sum / state.average.len() as f64;
+
+        let signal = state
+            .signal
+            .get_or_insert_with(|| DelayLine::new(lookahead - 1));
+        let delayed = if lookahead > 1 { signal.read() } else { v };
+        signal.write(v);
+
+        Ok(delayed * gain * makeup)
+    }
+}
+
+impl Display for Dynamics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} (threshold: {} dB, ratio: {}, attack: {}s, release: {}s, knee: {} dB, makeup: {} dB",
+            self.kind, self.threshold, self.ratio, self.attack, self.release, self.knee, self.makeup
+        )?;
+        if self.kind == DynamicsKind::Limiter {
+            write!(f, ", look-ahead: {}s", self.lookahead)?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gen::tests::{render, RATE};
+
+    use super::gain_to_db;
+
+    fn sine(effects: &str) -> Vec<f64> {
+        render(&format!(
+            "\"t\" 1s on 1 sine(200 hz, 0 rad, :) on * @ 1 {{ {effects} }}"
+        ))
+    }
+
+    /// Output level of a steady 0 dBFS input (the first half of a slow square), after settling.
+    fn settled_level(effects: &str) -> f64 {
+        let x = render(&format!(
+            "\"t\" 2s on 1 square(0.25 hz, 0 rad, naive, :) on * @ 1 {{ {effects} }}"
+        ));
+        gain_to_db(x[RATE * 3 / 2])
+    }
+
+    #[test]
+    fn steady_state_gain_follows_ratio_and_threshold() {
+        for (effects, expected) in [
+            // 20 dB over the threshold, 5 dB of it are left
+            ("compressor(threshold -20, ratio 4) :", -15.),
+            ("compressor(threshold -20, ratio 4, makeup 6) :", -9.),
+            ("compressor(threshold 6, ratio 4) :", 0.),
+            // 10 dB under the threshold become 20 dB
+            ("gains([0.1]) : expander(threshold -10, ratio 2) :", -30.),
+            ("limiter(threshold -6) :", -6.),
+        ] {
+            let level = settled_level(effects);
+            assert!((level - expected).abs() < 0.1, "{effects} {level:.2} dB");
+        }
+    }
+
+    #[test]
+    fn gates_stay_open_over_zero_crossings() {
+        for (level, effects) in [
+            ("", "gate() :"),
+            ("", "expander() :"),
+            // 10 dB over the threshold
+            ("gains([0.1]) :", "gate(threshold -30) :"),
+            ("gains([0.1]) :", "expander(threshold -30, ratio 4) :"),
+        ] {
+            let dry = sine(level);
+            let wet = sine(&format!("{level} {effects}"));
+
+            for (d, w) in dry.iter().zip(&wet).skip(RATE / 10) {
+                assert!((d - w).abs() < 1e-9, "{level} {effects}: {d} became {w}");
+            }
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use std::{f64::consts::TAU, fmt::Display};
+
+use crate::parse::{tokenizer::Number, Expression, ExpressionError};
+
+use super::{GenInfo, PerChannel};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterKind {
+    Lowpass,
+    Highpass,
+    /// Constant 0 dB peak gain.
+    Bandpass,
+    Notch,
+    LowShelf,
+    HighShelf,
+    Peak,
+}
+
+impl FilterKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "lowpass" => Self::Lowpass,
+            "highpass" => Self::Highpass,
+            "bandpass" => Self::Bandpass,
+            "notch" => Self::Notch,
+            "lowshelf" | "low_shelf" => Self::LowShelf,
+            "highshelf" | "high_shelf" => Self::HighShelf,
+            "peak" | "bell" => Self::Peak,
+
+            _ => return None,
+        })
+    }
+}
+
+impl Display for FilterKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FilterKind::Lowpass => write!(f, "lowpass"),
+            FilterKind::Highpass => write!(f, "highpass"),
+            FilterKind::Bandpass => write!(f, "bandpass"),
+            FilterKind::Notch => write!(f, "notch"),
+            FilterKind::LowShelf => write!(f, "low shelf"),
+            FilterKind::HighShelf => write!(f, "high shelf"),
+            FilterKind::Peak => write!(f, "peak"),
+        }
+    }
+}
+
+/// `a0` is 1.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Coefficients {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+}
+
+impl Coefficients {
+    /// From coefficients already divided by `a0`.
+    pub fn from_normalized([b0, b1, b2]: [f64; 3], [a1, a2]: [f64; 2]) -> Self {
+        Self { b0, b1, b2, a1, a2 }
+    }
+
+    /// From the RBJ Audio EQ Cookbook, `gain` is in dB and only used by shelves and peaks.
+    pub fn new(kind: FilterKind, samplerate: f64, freq: f64, q: f64, gain: f64) -> Self {
+        let freq = freq.clamp(1., samplerate * 0.49);
+        let q = q.max(0.01);
+
+        let w0 = TAU * freq / samplerate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2. * q);
+        let a = f64::powf(10., gain / 40.);
+        let sqrt_a_alpha = 2. * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match kind {
+            FilterKind::Lowpass => (
+                (1. - cos) / 2.,
+                1. - cos,
+                (1. - cos) / 2.,
+                1. + alpha,
+                -2. * cos,
+                1. - alpha,
+            ),
+            FilterKind::Highpass => (
+                (1. + cos) / 2.,
+                -(1. + cos),
+                (1. + cos) / 2.,
+                1. + alpha,
+                -2. * cos,
+                1. - alpha,
+            ),
+            FilterKind::Bandpass => (alpha, 0., -alpha, 1. + alpha, -2. * cos, 1. - alpha),
+            FilterKind::Notch => (1., -2. * cos, 1., 1. + alpha, -2. * cos, 1. - alpha),
+            FilterKind::Peak => (
+                1. + alpha * a,
+                -2. * cos,
+                1. - alpha * a,
+                1. + alpha / a,
+                -2. * cos,
+                1. - alpha / a,
+            ),
+            FilterKind::LowShelf => (
+                a * ((a + 1.) - (a - 1.) * cos + sqrt_a_alpha),
+                2. * a * ((a - 1.) - (a + 1.) * cos),
+                a * ((a + 1.) - (a - 1.) * cos - sqrt_a_alpha),
+                (a + 1.) + (a - 1.) * cos + sqrt_a_alpha,
+                -2. * ((a - 1.) + (a + 1.) * cos),
+                (a + 1.) + (a - 1.) * cos - sqrt_a_alpha,
+            ),
+            FilterKind::HighShelf => (
+                a * ((a + 1.) + (a - 1.) * cos + sqrt_a_alpha),
+                -2. * a * ((a - 1.) + (a + 1.) * cos),
+                a * ((a + 1.) + (a - 1.) * cos - sqrt_a_alpha),
+                (a + 1.) - (a - 1.) * cos + sqrt_a_alpha,
+                2. * ((a - 1.) - (a + 1.) * cos),
+                (a + 1.) - (a - 1.) * cos - sqrt_a_alpha,
+            ),
+        };
+
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Biquad {
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    pub fn process(&mut self, c: &Coefficients, x: f64) -> f64 {
+        let y = c.b0 * x + self.z1;
+        self.z1 = c.b1 * x - c.a1 * y + self.z2;
+        self.z2 = c.b2 * x - c.a2 * y;
+        y
+    }
+}
+
+#[derive(Debug)]
+pub struct Filter {
+    pub(crate) kind: FilterKind,
+    pub(crate) freq: Expression,
+    pub(crate) q: Expression,
+    /// In dB, for shelves and peaks.
+    pub(crate) gain: Expression,
+
+    /// The coefficients only get recalculated when the parameters change.
+    coefficients: Option<((f64, f64, f64, usize), Coefficients)>,
+    state: PerChannel<Biquad>,
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind, freq: Expression) -> Self {
+        Self {
+            kind,
+            freq,
+            q: Expression::Lit(Number::Real(std::f64::consts::FRAC_1_SQRT_2)),
+            gain: Expression::zero(),
+            coefficients: None,
+            state: PerChannel::default(),
+        }
+    }
+
+    pub fn apply(&mut self, v: f64, gi: GenInfo) -> Result<f64, ExpressionError> {
+        let params = (
+            self.freq.evaluate(Some(gi))?,
+            self.q.evaluate(Some(gi))?,
+            self.gain.evaluate(Some(gi))?,
+            gi.samplerate,
+        );
+
+        let c = match self.coefficients {
+            Some((p, c)) if p == params => c,
+            _ => {
+                let (freq, q, gain, samplerate) = params;
+                let c = Coefficients::new(self.kind, samplerate as f64, freq, q, gain);
+                self.coefficients = Some((params, c));
+                c
+            }
+        };
+
+        Ok(self.state.get(gi.channel).process(&c, v))
+    }
+}
+
+impl Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} Hz (q: {}", self.kind, self.freq, self.q)?;
+        if matches!(
+            self.kind,
+            FilterKind::LowShelf | FilterKind::HighShelf | FilterKind::Peak
+        ) {
+            write!(f, ", gain: {} dB", self.gain)?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gen::tests::{render, rms, RATE};
+
+    #[test]
+    fn gain_at_the_cutoff() {
+        for (filter, expected) in [
+            ("lowpass(1000 hz)", -3.01),
+            ("highpass(1000 hz)", -3.01),
+            ("bandpass(1000 hz)", 0.),
+            ("peak(1000 hz, gain 12)", 12.),
+            // shelves are half way there
+            ("lowshelf(1000 hz, gain 12)", 6.),
+            ("highshelf(1000 hz, gain 12)", 6.),
+        ] {
+            let x = render(&format!(
+                "\"t\" 0.5s on 1 sine(1000 hz, 0 rad, :) on * @ 1 {{ {filter} : }}"
+            ));
+
+            // skip the filter settling, then a whole number of cycles
+            let gain = 20. * f64::log10(rms(&x[RATE / 10..]) * 2f64.sqrt());
+            assert!((gain - expected).abs() < 0.1, "{filter}: {gain:.2} dB");
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use std::{f64::consts::TAU, fmt::Display};
+
+use crate::parse::{tokenizer::Number, Expression, ExpressionError};
+
+use super::{GenInfo, Oscillator, PerChannel};
+
+pub const MAX_OPERATORS: usize = 4;
+
+/// Which operators modulate each operator (all 0 based), in the style of the
+/// 4 operator DX algorithms. Operator 1 is always a carrier, so is every
+/// operator that doesn't modulate anything. With fewer operators the missing
+/// ones are just left out.
+pub const ALGORITHMS: [[&[usize]; MAX_OPERATORS]; 8] = [
+    // 4 -> 3 -> 2 -> 1
+    [&[1], &[2], &[3], &[]],
+    // (3 + 4) -> 2 -> 1
+    [&[1], &[2, 3], &[], &[]],
+    // (2 + (4 -> 3)) -> 1
+    [&[1, 2], &[], &[3], &[]],
+    // ((4 -> 2) + 3) -> 1
+    [&[1, 2], &[3], &[], &[]],
+    // 2 -> 1, 4 -> 3
+    [&[1], &[], &[3], &[]],
+    // 4 -> (1, 2, 3)
+    [&[3], &[3], &[3], &[]],
+    // 1, 2, 4 -> 3
+    [&[], &[], &[3], &[]],
+    // 1, 2, 3, 4
+    [&[], &[], &[], &[]],
+];
+
+/// Phase modulation synthesis with 2 to 4 operators.
+#[derive(Debug)]
+pub struct FmSource {
+    pub(crate) carrier: Expression,
+
+    /// Frequency of every operator relative to the carrier frequency.
+    pub(crate) ratios: Vec<Expression>,
+    /// Modulation index (radians) for modulators, output level for carriers.
+    pub(crate) indices: Vec<Expression>,
+    /// Index into [`ALGORITHMS`].
+    pub(crate) algorithm: usize,
+    /// Self modulation of the last operator.
+    pub(crate) feedback: Expression,
+
+    pub(crate) state: PerChannel<FmState>,
+}
+
+#[derive(Debug, Default)]
+pub struct FmState {
+    osc: [Oscillator; MAX_OPERATORS],
+    /// The last two outputs of the feedback operator.
+    feedback: [f64; 2],
+}
+
+impl FmSource {
+    /// A carrier with a single modulator.
+    pub fn new(carrier: Expression) -> Self {
+        Self {
+            carrier,
+            ratios: vec![Expression::Lit(Number::Integer(1)); 2],
+            indices: vec![Expression::Lit(Number::Integer(1)); 2],
+            algorithm: 0,
+            feedback: Expression::zero(),
+            state: PerChannel::default(),
+        }
+    }
+
+    pub fn operators(&self) -> usize {
+        self.ratios.len()
+    }
+
+    fn is_carrier(&self, op: usize) -> bool {
+        let algorithm = &ALGORITHMS[self.algorithm];
+        !(0..self.operators()).any(|o| algorithm[o].contains(&op))
+    }
+
+    pub fn gen(&mut self, gi: GenInfo) -> Result<f64, ExpressionError> {
+        let freq = self.carrier.evaluate(Some(gi))?;
+        let feedback = self.feedback.evaluate(Some(gi))?;
+
+        let n = self.operators();
+        let last = n - 1;
+
+        let mut out = [0.; MAX_OPERATORS];
+        let mut carriers = 0;
+        let mut v = 0.;
+
+        // modulators always come after the operators they modulate
+        for op in (0..n).rev() {
+            let ratio = self.ratios[op].evaluate(Some(gi))?;
+            let level = self.indices[op].evaluate(Some(gi))?;
+
+            let modulation: f64 = ALGORITHMS[self.algorithm][op]
+                .iter()
+                .filter(|m| **m < n)
+                .map(|m| out[*m])
+                .sum();
+
+            let state = self.state.get(gi.channel);
+            let cycles = state.osc[op].advance(gi.secs(), freq * ratio);
+
+            let modulation = if op == last {
+                modulation + feedback * (state.feedback[0] + state.feedback[1]) / 2.
+            } else {
+                modulation
+            };
+
+            let y = f64::sin(cycles * TAU + modulation);
+            if op == last {
+                state.feedback = [y, state.feedback[0]];
+            }
+
+            out[op] = level * y;
+
+            if self.is_carrier(op) {
+                carriers += 1;
+                v += out[op];
+            }
+        }
+
+        Ok(v / carriers as f64)
+    }
+}
+
+impl Display for FmSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let list = |l: &[Expression]| {
+            l.iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        write!(
+            f,
+            "{} Hz fm (algorithm: {}, ratios: [{}], indices: [{}], feedback: {})",
+            self.carrier,
+            self.algorithm + 1,
+            list(&self.ratios),
+            list(&self.indices),
+            self.feedback,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gen::tests::{power, render};
+
+    /// Amplitude of the sine at `freq`, which has to fall on a bin of `x`.
+    fn amplitude(x: &[f64], freq: f64) -> f64 {
+        2. * power(x, freq).sqrt() / x.len() as f64
+    }
+
+    #[test]
+    fn sidebands_follow_the_bessel_functions() {
+        // 1000 Hz modulated by 200 Hz with index 1.5
+        let x = render("\"t\" 1s on 1 fm(carrier 1000 hz, ratio 0.2, index 1.5, :) on * @ 1 {}");
+
+        // J0(1.5), J1(1.5), J2(1.5)
+        for (k, j) in [(0., 0.511828), (1., 0.557937), (2., 0.232088)] {
+            for freq in [1000. - k * 200., 1000. + k * 200.] {
+                let a = amplitude(&x, freq);
+                assert!((a - j).abs() < 0.01, "{a} at {freq} Hz, not {j}");
+            }
+        }
+    }
+
+    #[test]
+    fn feedback_adds_harmonics() {
+        let harmonic = |feedback: f64| {
+            // two carriers, the second one feeds back on itself
+            let x = render(&format!(
+                "\"t\" 1s on 1 fm(carrier 440 hz, ratios [1, 1], indices [1, 1], algo 8, feedback {feedback}, :) on * @ 1 {{}}"
+            ));
+            amplitude(&x, 880.) / amplitude(&x, 440.)
+        };
+
+        assert!(harmonic(0.) < 1e-4, "{}", harmonic(0.));
+        assert!(harmonic(1.) > 0.05, "{}", harmonic(1.));
+    }
+}
@@ -0,0 +1,191 @@
+use std::{f64::consts::TAU, fmt::Display};
+
+use crate::{
+    parse::{Expression, ExpressionError},
+    wav::reader::Wave,
+};
+
+use super::{noise::Rng, sample::interpolate, GenInfo, PerChannel};
+
+/// More grains per second only cost time, they sound like noise long before.
+pub const MAX_DENSITY: f64 = 1000.;
+
+#[derive(Debug)]
+pub struct GrainsSource {
+    pub(crate) path: String,
+    pub(crate) wave: Wave,
+
+    /// Per second, up to `MAX_DENSITY`.
+    pub(crate) density: Expression,
+    /// In seconds.
+    pub(crate) size: f64,
+    /// 0..1 of the file.
+    pub(crate) position: Expression,
+    /// In semitones.
+    pub(crate) pitch: Expression,
+
+    /// Random offset of the position, 0..1 of the file.
+    pub(crate) spread: Expression,
+    /// In semitones.
+    pub(crate) detune: Expression,
+    pub(crate) seed: u64,
+
+    pub(crate) clouds: PerChannel<Cloud>,
+}
+
+#[derive(Debug, Default)]
+pub struct Cloud {
+    rng: Option<Rng>,
+    grains: Vec<Grain>,
+
+    /// Seconds until the next grain starts.
+    wait: f64,
+    last_secs: Option<f64>,
+}
+
+#[derive(Debug)]
+struct Grain {
+    /// In seconds.
+    start: f64,
+    speed: f64,
+    age: f64,
+}
+
+impl GrainsSource {
+    pub fn new(
+        path: String,
+        wave: Wave,
+        density: Expression,
+        size: f64,
+        position: Expression,
+        pitch: Expression,
+    ) -> Self {
+        Self {
+            path,
+            wave,
+            density,
+            size,
+            position,
+            pitch,
+            spread: Expression::zero(),
+            detune: Expression::zero(),
+            seed: 0,
+            clouds: PerChannel::default(),
+        }
+    }
+
+    pub fn file_length(&self) -> f64 {
+        self.wave.frames() as f64 / self.wave.desc.samplerate() as f64
+    }
+
+    pub fn gen(&mut self, gi: GenInfo) -> Result<f64, ExpressionError> {
+        let density = self.density.evaluate(Some(gi))?.clamp(0., MAX_DENSITY);
+        let file_length = self.file_length();
+        let size = self.size.max(f64::EPSILON);
+
+        let secs = gi.secs();
+        let cloud = self.clouds.get(gi.channel);
+        let mut rng = cloud
+            .rng
+            .take()
+            .unwrap_or_else(|| Rng::for_channel(self.seed, gi.channel));
+
+        let dt = match cloud.last_secs {
+            Some(last) => secs - last,
+            None => 0.,
+        };
+        cloud.last_secs = Some(secs);
+
+        // grains start as soon as the density goes up again
+        cloud.wait = if density > 0. { cloud.wait - dt } else { 0. };
+        while density > 0. && cloud.wait <= 0. {
+            let position = self.position.evaluate(Some(gi))?;
+            let pitch = self.pitch.evaluate(Some(gi))?;
+            let spread = self.spread.evaluate(Some(gi))?;
+            let detune = self.detune.evaluate(Some(gi))?;
+
+            let start = (position + spread * rng.next_bipolar()) * file_length;
+            let semitones = pitch + detune * rng.next_bipolar();
+
+            cloud.grains.push(Grain {
+                start: start.rem_euclid(file_length.max(f64::EPSILON)),
+                speed: f64::powf(2., semitones / 12.),
+                // the grain starts somewhere in this sample
+                age: -cloud.wait,
+            });
+
+            cloud.wait += 1. / density;
+        }
+
+        let rate = self.wave.desc.samplerate() as f64;
+        let channel = gi.channel % self.wave.desc.channels() as usize;
+
+        let mut v = 0.;
+        for grain in &mut cloud.grains {
+            let window = 0.5 - 0.5 * f64::cos(TAU * grain.age / size);
+            let frame = (grain.start + grain.age * grain.speed) * rate;
+
+            v += window * interpolate(&self.wave, frame, channel);
+            grain.age += dt;
+        }
+        cloud.grains.retain(|g| g.age < size);
+
+        cloud.rng = Some(rng);
+
+        // overlapping grains are mostly uncorrelated, so they add up in power
+        let overlap = density * size / 2.;
+        Ok(v / overlap.sqrt().max(1.))
+    }
+}
+
+impl Display for GrainsSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "grains \"{}\" (density: {}, size: {}s, position: {}, pitch: {}, spread: {}, detune: {}, seed: {})",
+            self.path,
+            self.density,
+            self.size,
+            self.position,
+            self.pitch,
+            self.spread,
+            self.detune,
+            self.seed
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gen::tests::{render, write_wave, RATE};
+
+    #[test]
+    fn grains_start_at_the_density() {
+        let dc = vec![1.; RATE];
+        let path = write_wave("grains-dc", RATE, &dc);
+
+        let x = render(&format!(
+            "\"t\" 1s on 1 grains({path:?}, 20, 10ms, 0, 0, :) on * @ 1 {{}}"
+        ));
+        let starts = x.windows(2).filter(|w| w[0] < 1e-9 && w[1] >= 1e-9).count();
+        assert_eq!(starts, 20);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn grains_are_seeded() {
+        let ramp: Vec<f64> = (0..RATE).map(|i| i as f64 / RATE as f64).collect();
+        let path = write_wave("grains-ramp", RATE, &ramp);
+
+        let grains = |seed: u64| {
+            render(&format!(
+                "\"t\" 0.5s on 1 grains({path:?}, 50, 50ms, 0.5, 0, spread 0.3, detune 2, seed {seed}, :) on * @ 1 {{}}"
+            ))
+        };
+        assert!(grains(7) == grains(7));
+        assert!(grains(7) != grains(8));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,809 @@
+use std::{
+    f64::consts::{PI, TAU},
+    fmt::Display,
+};
+
+use crate::parse::{Expression, ExpressionError};
+
+pub use self::{
+    additive::AdditiveSource,
+    convolve::Convolution,
+    delay::Delay,
+    distortion::{Bitcrush, Curve, Drive, Waveshaper},
+    dynamics::{Dynamics, DynamicsKind},
+    filter::{Filter, FilterKind},
+    fm::FmSource,
+    grains::GrainsSource,
+    modulation::{Modulation, ModulationKind},
+    noise::{NoiseColor, NoiseSource},
+    pan::{Balance, Gains, Pan, PanLaw, Width},
+    pluck::PluckSource,
+    reverb::Reverb,
+    sample::SampleSource,
+    signal::{DtmfSource, ImpulseSource, SweepCurve, SweepSource},
+    wavetable::WavetableSource,
+};
+
+pub mod additive;
+pub mod convolve;
+pub mod delay;
+pub mod distortion;
+pub mod dynamics;
+pub mod filter;
+pub mod fm;
+pub mod grains;
+pub mod modulation;
+pub mod noise;
+pub mod pan;
+pub mod pluck;
+pub mod reverb;
+pub mod sample;
+pub mod signal;
+pub mod wavetable;
+
+#[derive(Debug)]
+pub struct Song {
+    pub name: String,
+
+    pub(crate) channels: usize,
+    pub(crate) length_s: f64,
+
+    pub(crate) sources: Vec<Source>,
+    /// Applied to the mix of all sources.
+    pub(crate) effects: Vec<Effect>,
+}
+
+impl Song {
+    pub fn length(&self) -> f64 {
+        self.length_s
+    }
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+}
+
+pub fn print_song(s: &Song) {
+    println!("'{}': {}s, {} channels", s.name, s.length(), s.channels);
+    print_effects(&s.effects, "  ");
+
+    for s in &s.sources {
+        print!("  ");
+        match &s.ty {
+            SourceType::Periodic {
+                freq, phase, ty, ..
+            } => {
+                print!("{freq} Hz (phase: {phase}) {ty}",);
+            }
+            SourceType::Sample(s) => print!("{s}"),
+            SourceType::Noise(n) => print!("{n}"),
+            SourceType::Additive(a) => print!("{a}"),
+            SourceType::Fm(fm) => print!("{fm}"),
+            SourceType::Pluck(p) => print!("{p}"),
+            SourceType::Wavetable(w) => print!("{w}"),
+            SourceType::Grains(g) => print!("{g}"),
+            SourceType::Sweep(s) => print!("{s}"),
+            SourceType::Impulse(i) => print!("{i}"),
+            SourceType::Dtmf(d) => print!("{d}"),
+            SourceType::Silence => print!("silence"),
+        }
+
+        println!(
+            " {}:{}, volume: {}, channels: {}",
+            s.start, s.end, s.volume, s.channels
+        );
+
+        print_effects(&s.effects, "    ");
+    }
+}
+
+fn print_effects(effects: &[Effect], indent: &str) {
+    for e in effects {
+        print!("{indent}");
+        match &e.ty {
+            EffectType::FadeIn => print!("fade in"),
+            EffectType::FadeOut => print!("fade out"),
+            EffectType::Filter(f) => print!("{f}"),
+            EffectType::Delay(d) => print!("{d}"),
+            EffectType::Reverb(r) => print!("{r}"),
+            EffectType::Convolution(c) => print!("{c}"),
+            EffectType::Dynamics(d) => print!("{d}"),
+            EffectType::Modulation(m) => print!("{m}"),
+            EffectType::Drive(d) => print!("{d}"),
+            EffectType::Waveshaper(w) => print!("{w}"),
+            EffectType::Bitcrush(b) => print!("{b}"),
+            EffectType::Pan(p) => print!("{p}"),
+            EffectType::Balance(b) => print!("{b}"),
+            EffectType::Width(w) => print!("{w}"),
+            EffectType::Gains(g) => print!("{g}"),
+        }
+        println!(" {}:{}", e.start, e.end);
+    }
+}
+
+#[derive(Debug)]
+pub struct Source {
+    pub(crate) ty: SourceType,
+    pub(crate) start: f64,
+    pub(crate) end: f64,
+    pub(crate) volume: Expression,
+    pub(crate) channels: Channels,
+
+    pub(crate) effects: Vec<Effect>,
+}
+
+#[derive(Debug)]
+pub enum Channels {
+    List(Vec<usize>),
+    One(usize),
+    All,
+}
+
+impl Channels {
+    pub fn has(&self, c: usize) -> bool {
+        match self {
+            Self::List(l) => l.contains(&c),
+            Self::One(ch) => c == *ch,
+            Self::All => true,
+        }
+    }
+}
+
+impl Display for Channels {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Channels::List(l) => write!(f, "{l:?}"),
+            Channels::One(c) => write!(f, "{c}"),
+            Channels::All => write!(f, "all"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SourceType {
+    Periodic {
+        freq: Expression,
+        phase: Expression,
+        ty: PeriodicSource,
+        band_limited: bool,
+
+        osc: PerChannel<Oscillator>,
+    },
+    Sample(SampleSource),
+    Noise(NoiseSource),
+    Additive(AdditiveSource),
+    Fm(FmSource),
+    Pluck(PluckSource),
+    Wavetable(WavetableSource),
+    Grains(GrainsSource),
+    Sweep(SweepSource),
+    Impulse(ImpulseSource),
+    Dtmf(DtmfSource),
+    Silence,
+}
+
+#[derive(Debug)]
+pub enum PeriodicSource {
+    Sine,
+    Saw,
+    Square,
+    Triangle,
+    /// Square wave with a variable duty cycle, `width` is the high part of the period (0..1).
+    Pulse {
+        width: Expression,
+    },
+}
+
+impl Display for PeriodicSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PeriodicSource::Sine => write!(f, "sine"),
+            PeriodicSource::Saw => write!(f, "saw"),
+            PeriodicSource::Square => write!(f, "square"),
+            PeriodicSource::Triangle => write!(f, "triangle"),
+            PeriodicSource::Pulse { width } => write!(f, "pulse (width: {width})"),
+        }
+    }
+}
+
+impl SourceType {
+    pub fn gen(&mut self, gi: GenInfo) -> Result<f64, ExpressionError> {
+        Ok(match self {
+            Self::Periodic {
+                freq,
+                phase,
+                ty,
+                band_limited,
+                osc,
+            } => {
+                let phase = phase.evaluate(Some(gi))?;
+                let freq = freq.evaluate(Some(gi))?;
+
+                let osc = osc.get(gi.channel);
+                let cycles = osc.advance(gi.secs(), freq);
+
+                let width = match ty {
+                    PeriodicSource::Pulse { width } => width.evaluate(Some(gi))?.clamp(0., 1.),
+                    _ => 0.5,
+                };
+
+                if *band_limited && !matches!(ty, PeriodicSource::Sine) {
+                    let p = (cycles + phase / TAU).rem_euclid(1.);
+                    let dt = osc.step().abs();
+
+                    match ty {
+                        PeriodicSource::Saw => saw_bl(p, dt),
+                        PeriodicSource::Square => square_bl(p, dt),
+                        PeriodicSource::Triangle => triangle_bl(p, dt),
+                        PeriodicSource::Pulse { .. } => pulse_bl(p, dt, width),
+
+                        PeriodicSource::Sine => unreachable!(),
+                    }
+                } else {
+                    // the waveforms get the accumulated phase as time at 1 Hz
+                    match ty {
+                        PeriodicSource::Sine => sine(cycles, 1., phase),
+                        PeriodicSource::Saw => saw(cycles, 1., phase / TAU),
+                        PeriodicSource::Square => square(cycles, 1., phase / TAU),
+                        PeriodicSource::Triangle => triangle(cycles, 1., phase / TAU),
+                        PeriodicSource::Pulse { .. } => pulse(cycles, 1., phase / TAU, width),
+                    }
+                }
+            }
+            Self::Sample(s) => s.gen(gi)?,
+            Self::Noise(n) => n.gen(gi),
+            Self::Additive(a) => a.gen(gi)?,
+            Self::Fm(fm) => fm.gen(gi)?,
+            Self::Pluck(p) => p.gen(gi)?,
+            Self::Wavetable(w) => w.gen(gi)?,
+            Self::Grains(g) => g.gen(gi)?,
+            Self::Sweep(s) => s.gen(gi)?,
+            Self::Impulse(i) => i.gen(gi),
+            Self::Dtmf(d) => d.gen(gi),
+            Self::Silence => 0.,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum EffectType {
+    FadeIn,
+    FadeOut,
+    Filter(Filter),
+    Delay(Delay),
+    Reverb(Reverb),
+    Convolution(Convolution),
+    Dynamics(Dynamics),
+    Modulation(Modulation),
+    Drive(Drive),
+    Waveshaper(Waveshaper),
+    Bitcrush(Bitcrush),
+    Pan(Pan),
+    Balance(Balance),
+    Width(Width),
+    Gains(Gains),
+}
+
+impl EffectType {
+    pub fn apply(&mut self, v: f64, gi: GenInfo) -> Result<f64, ExpressionError> {
+        Ok(match self {
+            Self::FadeIn => v * gi.t,
+            Self::FadeOut => v * (1. - gi.t),
+            Self::Filter(f) => f.apply(v, gi)?,
+            Self::Delay(d) => d.apply(v, gi)?,
+            Self::Reverb(r) => r.apply(v, gi)?,
+            Self::Convolution(c) => c.apply(v, gi)?,
+            Self::Dynamics(d) => d.apply(v, gi)?,
+            Self::Modulation(m) => m.apply(v, gi)?,
+            Self::Drive(d) => d.apply(v, gi)?,
+            Self::Waveshaper(w) => w.apply(v, gi)?,
+            Self::Bitcrush(b) => b.apply(v, gi)?,
+            Self::Pan(p) => p.apply(v, gi)?,
+            Self::Balance(b) => b.apply(v, gi)?,
+            Self::Width(w) => w.apply(v, gi)?,
+            Self::Gains(g) => g.apply(v, gi)?,
+        })
+    }
+
+    /// Seconds the effect keeps ringing after its input stops.
+    pub fn tail(&self) -> f64 {
+        match self {
+            Self::Delay(d) => d.tail(),
+            Self::Reverb(r) => r.tail(),
+            Self::Convolution(c) => c.tail(),
+            Self::Dynamics(d) => d.tail(),
+            Self::Modulation(m) => m.tail(),
+            Self::Width(w) => w.tail(),
+            _ => 0.,
+        }
+    }
+
+    /// Effects moving the signal between channels, a source has to be on all of them.
+    pub fn spans_channels(&self) -> bool {
+        match self {
+            Self::Delay(d) => d.ping_pong,
+            Self::Pan(_) | Self::Balance(_) | Self::Width(_) | Self::Gains(_) => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Effect {
+    pub(crate) ty: EffectType,
+    pub(crate) start: f64,
+    pub(crate) end: f64,
+}
+
+impl Effect {
+    pub fn apply(&mut self, v: f64, gi: GenInfo) -> Result<f64, ExpressionError> {
+        self.ty.apply(v, gi)
+    }
+}
+
+/// Runs `v` through the effects that are active at `gi`, `gi` is the info of their parent.
+pub fn apply_effects(
+    effects: &mut [Effect],
+    mut v: f64,
+    gi: GenInfo,
+) -> Result<f64, ExpressionError> {
+    for e in effects {
+        let gi_e = GenInfo::new(gi, e.start, e.end);
+
+        if (e.start..=e.end).contains(&gi.t) {
+            v = e.apply(v, gi_e)?;
+        } else if gi.t > e.end && gi.t <= e.end + e.ty.tail() / gi.len_s {
+            // the effect rings out on top of the signal that now passes through
+            v += e.apply(0., gi_e)?;
+        }
+    }
+
+    Ok(v)
+}
+
+impl Source {
+    pub fn gen(&mut self, gi: GenInfo) -> Result<f64, ExpressionError> {
+        // past the end only the effect tails are left
+        let v = if gi.t <= 1. { self.ty.gen(gi)? } else { 0. };
+        let v = apply_effects(&mut self.effects, v, gi)?;
+
+        Ok(v * self.volume.evaluate(Some(gi))?)
+    }
+
+    /// Seconds the source's effects ring past its end, `len_s` is the length of the source.
+    pub fn tail(&self, len_s: f64) -> f64 {
+        self.effects
+            .iter()
+            .map(|e| (e.end - 1.) * len_s + e.ty.tail())
+            .fold(0., f64::max)
+    }
+
+    pub fn length(&self) -> f64 {
+        self.end - self.start
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GenInfo {
+    pub(crate) channel: usize,
+    /// Channels in the song.
+    pub(crate) channels: usize,
+    pub(crate) samplerate: usize,
+
+    /// Position in the current frame (song, source or effect), normalized to 0..1.
+    pub(crate) t: f64,
+    /// Length of the current frame in seconds.
+    pub(crate) len_s: f64,
+
+    pub(crate) song_t: f64,
+    pub(crate) song_secs: f64,
+
+    /// The sample going into a waveshaper, `x` in its transfer expression.
+    pub(crate) input: Option<f64>,
+}
+
+impl GenInfo {
+    /// Info for the whole song at `secs` seconds.
+    pub fn for_song(song: &Song, channel: usize, samplerate: usize, secs: f64) -> Self {
+        Self {
+            channel,
+            channels: song.channels,
+            samplerate,
+            t: secs / song.length_s,
+            len_s: song.length_s,
+            song_t: secs / song.length_s,
+            song_secs: secs,
+            input: None,
+        }
+    }
+
+    pub fn new(parent: GenInfo, start: f64, end: f64) -> Self {
+        Self {
+            t: (parent.t - start) / (end - start),
+            len_s: (end - start) * parent.len_s,
+            ..parent
+        }
+    }
+
+    /// Seconds elapsed since the start of the current frame.
+    pub fn secs(&self) -> f64 {
+        self.t * self.len_s
+    }
+}
+
+/// Integrates the (possibly changing) frequency of an oscillator into its phase.
+#[derive(Debug, Default, Clone)]
+pub struct Oscillator {
+    /// In cycles, kept in 0..1.
+    phase: f64,
+    /// Phase increment of the last step.
+    step: f64,
+    last_secs: Option<f64>,
+}
+
+impl Oscillator {
+    /// Moves the oscillator to `secs`, running at `freq` Hz since the last call.
+    /// Returns the phase in cycles.
+    pub fn advance(&mut self, secs: f64, freq: f64) -> f64 {
+        if let Some(last) = self.last_secs {
+            self.step = (secs - last) * freq;
+            self.phase = (self.phase + self.step).rem_euclid(1.);
+        }
+        self.last_secs = Some(secs);
+
+        self.phase
+    }
+
+    /// How many cycles the last [`Oscillator::advance`] moved.
+    pub fn step(&self) -> f64 {
+        self.step
+    }
+}
+
+/// State kept separately for every channel a source plays on.
+#[derive(Debug)]
+pub struct PerChannel<T>(Vec<T>);
+
+impl<T> Default for PerChannel<T> {
+    fn default() -> Self {
+        Self(vec![])
+    }
+}
+
+impl<T: Default> PerChannel<T> {
+    pub fn get(&mut self, channel: usize) -> &mut T {
+        if channel >= self.0.len() {
+            self.0.resize_with(channel + 1, T::default);
+        }
+
+        &mut self.0[channel]
+    }
+}
+
+pub fn get_sample(s: &mut Song, gi: GenInfo) -> Result<f64, ExpressionError> {
+    let mut mixed = 0.;
+
+    for src in &mut s.sources {
+        let len_s = src.length() * gi.len_s;
+        let end = src.end + src.tail(len_s) / gi.len_s;
+        if !src.channels.has(gi.channel) || !(src.start..=end).contains(&gi.t) {
+            continue;
+        }
+
+        let gi = GenInfo::new(gi, src.start, src.end);
+        let v = src.gen(gi)?;
+
+        mixed = mix(mixed, v);
+    }
+
+    apply_effects(&mut s.effects, mixed, gi)
+}
+
+pub fn mix(v1: f64, v2: f64) -> f64 {
+    v1 + v2
+}
+
+/// Mixes an effect's output into its input, a `mix` of 0 is only the dry signal and 1 only the wet one.
+pub fn dry_wet(dry: f64, wet: f64, mix: f64) -> f64 {
+    lerp(mix.clamp(0., 1.), dry, wet)
+}
+
+pub fn lerp(t: f64, s: f64, e: f64) -> f64 {
+    s * (1. - t) + e * t
+}
+pub fn sin_interpolate(t: f64, s: f64, e: f64) -> f64 {
+    if t <= 0. {
+        0.
+    } else if t >= 1. {
+        1.
+    } else {
+        let t = f64::sin(t * std::f64::consts::FRAC_PI_2);
+        s * (1. - t) + e * t
+    }
+}
+
+pub fn sine(t: f64, freq: f64, phase: f64) -> f64 {
+    f64::sin(t * freq * TAU + phase)
+}
+pub fn saw(t: f64, freq: f64, phase: f64) -> f64 {
+    f64::fract(t * freq + phase) * 2. - 1.
+}
+pub fn square(t: f64, freq: f64, phase: f64) -> f64 {
+    let x = t + phase;
+
+    let freq = 1. / freq;
+    if x % freq < freq / 2. {
+        1.
+    } else {
+        -1.
+    }
+}
+pub fn triangle(t: f64, freq: f64, phase: f64) -> f64 {
+    ((f64::fract(t * freq + phase) * 2. - 1.).abs() - 0.5) * 2.
+}
+pub fn pulse(t: f64, freq: f64, phase: f64, width: f64) -> f64 {
+    if f64::fract(t * freq + phase) < width {
+        1.
+    } else {
+        -1.
+    }
+}
+
+/// Residual of a band-limited step at `t` (in cycles), `dt` is the phase increment per sample.
+pub fn poly_blep(t: f64, dt: f64) -> f64 {
+    if t < dt {
+        let t = t / dt;
+        t + t - t * t - 1.
+    } else if t > 1. - dt {
+        let t = (t - 1.) / dt;
+        t * t + t + t + 1.
+    } else {
+        0.
+    }
+}
+
+/// Residual of a band-limited ramp (integrated [`poly_blep`]), scaled to a slope change of 1 per sample.
+pub fn poly_blamp(t: f64, dt: f64) -> f64 {
+    if t < dt {
+        let t = t / dt - 1.;
+        -t * t * t / 3.
+    } else if t > 1. - dt {
+        let t = (t - 1.) / dt + 1.;
+        t * t * t / 3.
+    } else {
+        0.
+    }
+}
+
+// band-limited versions of the waveforms above, `p` is the phase in cycles (0..1)
+pub fn saw_bl(p: f64, dt: f64) -> f64 {
+    saw(p, 1., 0.) - poly_blep(p, dt)
+}
+pub fn square_bl(p: f64, dt: f64) -> f64 {
+    square(p, 1., 0.) + poly_blep(p, dt) - poly_blep((p + 0.5).fract(), dt)
+}
+pub fn pulse_bl(p: f64, dt: f64, width: f64) -> f64 {
+    pulse(p, 1., 0., width) + poly_blep(p, dt) - poly_blep((p + 1. - width).fract(), dt)
+}
+pub fn triangle_bl(p: f64, dt: f64) -> f64 {
+    // the slope flips between +4 and -4 per cycle at both corners
+    triangle(p, 1., 0.) - 4. * dt * (poly_blamp(p, dt) - poly_blamp((p + 0.5).fract(), dt))
+}
+
+pub fn harmonic(nth: usize, t: f64, freq: f64, phase: f64) -> f64 {
+    sine(t, freq * nth as f64, phase)
+}
+
+pub fn overtone(nth: usize, t: f64, freq: f64, phase: f64) -> f64 {
+    harmonic(nth, t, freq, phase + PI)
+}
+
+pub fn harmonics(n: usize, t: f64, freq: f64, phase: f64) -> Vec<f64> {
+    (0..n).map(|i| harmonic(i, t, freq, phase)).collect()
+}
+
+pub fn overtones(n: usize, t: f64, freq: f64, phase: f64) -> Vec<f64> {
+    harmonics(n, t, freq, phase + PI)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{f64::consts::TAU, path::PathBuf};
+
+    use crate::{
+        parse,
+        pcm::Renderer,
+        wav::{self, SampleFormat},
+    };
+
+    pub const RATE: usize = 44100;
+
+    /// Renders a song, one buffer per channel.
+    pub fn render_channels(src: &str) -> Vec<Vec<f64>> {
+        let mut song = parse::get_song("test", src).unwrap();
+        let mut renderer = Renderer::new(&mut song, RATE);
+        let channels = renderer.channels();
+
+        let mut out = vec![0.; renderer.frames() * channels];
+        renderer.render_block(&mut out).unwrap();
+
+        (0..channels)
+            .map(|c| out.iter().skip(c).step_by(channels).copied().collect())
+            .collect()
+    }
+
+    /// Renders the first channel of a song.
+    pub fn render(src: &str) -> Vec<f64> {
+        render_channels(src).swap_remove(0)
+    }
+
+    /// Writes a mono 64 bit float wav into the temp dir, for songs to load.
+    pub fn write_wave(name: &str, samplerate: usize, samples: &[f64]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("wavgen-test-{}-{name}.wav", std::process::id()));
+
+        let data: Vec<u8> = samples.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let file = std::fs::File::create(&path).unwrap();
+        wav::write_to_wav(1, samplerate, SampleFormat::Float(64), &data, file).unwrap();
+
+        path
+    }
+
+    /// Root mean square of `x`.
+    pub fn rms(x: &[f64]) -> f64 {
+        (x.iter().map(|v| v * v).sum::<f64>() / x.len() as f64).sqrt()
+    }
+
+    /// Power of `x` at `freq` Hz (Goertzel).
+    pub fn power(x: &[f64], freq: f64) -> f64 {
+        let coeff = 2. * f64::cos(TAU * freq / RATE as f64);
+
+        let (mut s1, mut s2) = (0., 0.);
+        for v in x {
+            let s = v + coeff * s1 - s2;
+            s2 = s1;
+            s1 = s;
+        }
+
+        s1 * s1 + s2 * s2 - coeff * s1 * s2
+    }
+
+    /// Ratio of the power of aliased partials to the power of the real ones.
+    pub fn alias_ratio(x: &[f64], f0: usize) -> f64 {
+        let nyquist = RATE / 2;
+
+        let harmonics: f64 = (1..)
+            .map(|k| k * f0)
+            .take_while(|f| *f < nyquist)
+            .map(|f| power(x, f as f64))
+            .sum();
+
+        let mut aliases = std::collections::BTreeSet::new();
+        for k in (nyquist / f0 + 1)..(nyquist / f0 * 8) {
+            let f = k * f0 % RATE;
+            let f = if f > nyquist { RATE - f } else { f };
+
+            if f % f0 != 0 {
+                aliases.insert(f);
+            }
+        }
+        let aliases: f64 = aliases.into_iter().map(|f| power(x, f as f64)).sum();
+
+        aliases / harmonics
+    }
+
+    #[test]
+    fn band_limited_waveforms_dont_alias() {
+        // 10 Hz bins, so every partial and alias lands on a bin
+        const F0: usize = 3010;
+
+        for wave in ["saw", "square", "tri", "pulse"] {
+            let args = if wave == "pulse" { "0.3," } else { "" };
+
+            let naive = render(&format!(
+                "\"t\" 0.1s on 1 {wave}({F0} hz, 0 rad, {args} naive, :) on * @ 1 {{}}"
+            ));
+            let bl = render(&format!(
+                "\"t\" 0.1s on 1 {wave}({F0} hz, 0 rad, {args} :) on * @ 1 {{}}"
+            ));
+
+            let naive = 10. * alias_ratio(&naive, F0).log10();
+            let bl = 10. * alias_ratio(&bl, F0).log10();
+
+            assert!(bl < -25., "{wave}: aliases at {bl:.1} dB");
+            assert!(
+                bl < naive - 10.,
+                "{wave}: {bl:.1} dB vs {naive:.1} dB naive"
+            );
+        }
+    }
+
+    #[test]
+    fn pulse_width_is_the_duty_cycle() {
+        // a pulse is 1 for `width` of the cycle and -1 for the rest
+        let duty = |x: &[f64]| (x.iter().sum::<f64>() / x.len() as f64 + 1.) / 2.;
+
+        for width in [0.1, 0.3, 0.5, 0.8] {
+            let x = render(&format!(
+                "\"t\" 1s on 1 pulse(100 hz, 0 rad, {width}, :) on * @ 1 {{}}"
+            ));
+
+            let d = duty(&x);
+            assert!((d - width).abs() < 0.005, "{width}: duty cycle {d}");
+        }
+
+        // ten cycles at the start and the end of a sweep from 0.2 to 0.8
+        let x = render("\"t\" 1s on 1 pulse(100 hz, 0 rad, 0.2 + 0.6 * t, :) on * @ 1 {}");
+        for (window, width) in [(0..RATE / 10, 0.23), (RATE * 9 / 10..RATE, 0.77)] {
+            let d = duty(&x[window]);
+            assert!((d - width).abs() < 0.01, "{width}: duty cycle {d}");
+        }
+    }
+
+    /// Frequency around `secs` from the spacing of the rising zero crossings near it.
+    fn frequency_at(x: &[f64], secs: f64) -> f64 {
+        let window = (secs - 0.01) * RATE as f64..(secs + 0.01) * RATE as f64;
+
+        let crossings: Vec<f64> = (1..x.len())
+            .filter(|n| x[n - 1] < 0. && x[*n] >= 0.)
+            .map(|n| n as f64 - 1. + x[n - 1] / (x[n - 1] - x[n]))
+            .filter(|n| window.contains(n))
+            .collect();
+
+        let periods = (crossings.len() - 1) as f64;
+        RATE as f64 * periods / (crossings[crossings.len() - 1] - crossings[0])
+    }
+
+    #[test]
+    fn glissandos_integrate_their_frequency() {
+        let x = render("\"t\" 1s on 1 sine(440 + t * 440 hz, 0 rad, :) on * @ 1 {}");
+
+        for secs in [0.1, 0.5, 0.9] {
+            let f = frequency_at(&x, secs);
+            let expected = 440. + secs * 440.;
+            assert!(
+                (f - expected).abs() < 1.,
+                "{f} Hz at {secs}s, not {expected}"
+            );
+        }
+
+        // no jumps, every step is within what the highest frequency can do
+        let max_step = TAU * 880. / RATE as f64;
+        for (n, w) in x.windows(2).enumerate() {
+            assert!((w[1] - w[0]).abs() <= max_step, "jump at {n}");
+        }
+    }
+
+    #[test]
+    fn hertz_dont_depend_on_the_source_length() {
+        for (len, frame, secs) in [
+            ("0.5s", ":", 0.25),
+            ("3s", ":", 0.25),
+            ("3s", "1s:2s", 1.25),
+        ] {
+            let x = render(&format!(
+                "\"t\" {len} on 1 sine(440 hz, 0 rad, {frame}) on * @ 1 {{}}"
+            ));
+
+            let f = frequency_at(&x, secs);
+            assert!((f - 440.).abs() < 0.5, "{len} {frame}: {f} Hz");
+        }
+    }
+
+    #[test]
+    fn time_variables() {
+        for (var, expected) in [
+            ("t", 0.75),
+            ("secs", 0.75),
+            ("song_t", 0.625),
+            ("song_secs", 1.25),
+        ] {
+            // a source from 0.5 s to 1.5 s of a 2 s song
+            let x = render(&format!(
+                "\"t\" 2s on 1 sine(440 hz, 0 rad, 0.5s:1.5s) on * @ 1 {{ waveshape({var}) : }}"
+            ));
+
+            let v = x[RATE * 5 / 4];
+            assert!((v - expected).abs() < 1e-9, "{var} = {v}");
+        }
+    }
+}
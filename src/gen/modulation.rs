@@ -0,0 +1,276 @@
+use std::{
+    f64::consts::{PI, TAU},
+    fmt::Display,
+};
+
+use crate::parse::{tokenizer::Number, Expression, ExpressionError};
+
+use super::{
+    delay::{self, DelayLine},
+    dry_wet, GenInfo, PerChannel,
+};
+
+const PHASER_STAGES: usize = 4;
+/// The phaser sweeps two octaves either side of this at full depth.
+const PHASER_CENTER: f64 = 400.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModulationKind {
+    /// Modulated amplitude.
+    Tremolo,
+    /// Modulated delay, only the wet signal.
+    Vibrato,
+    Chorus,
+    Flanger,
+    /// Swept allpasses.
+    Phaser,
+}
+
+impl ModulationKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "tremolo" => Self::Tremolo,
+            "vibrato" => Self::Vibrato,
+            "chorus" => Self::Chorus,
+            "flanger" => Self::Flanger,
+            "phaser" => Self::Phaser,
+
+            _ => return None,
+        })
+    }
+
+    /// Shortest and longest delay in seconds, at full depth.
+    fn delays(self) -> (f64, f64) {
+        match self {
+            Self::Vibrato => (0.0005, 0.0055),
+            Self::Chorus => (0.01, 0.03),
+            Self::Flanger => (0.0002, 0.0052),
+            Self::Tremolo | Self::Phaser => (0., 0.),
+        }
+    }
+}
+
+impl Display for ModulationKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModulationKind::Tremolo => write!(f, "tremolo"),
+            ModulationKind::Vibrato => write!(f, "vibrato"),
+            ModulationKind::Chorus => write!(f, "chorus"),
+            ModulationKind::Flanger => write!(f, "flanger"),
+            ModulationKind::Phaser => write!(f, "phaser"),
+        }
+    }
+}
+
+/// First order allpass, `(x1, y1)`.
+type Allpass = (f64, f64);
+
+#[derive(Debug)]
+pub struct ModulationState {
+    /// Of the LFO, in cycles.
+    phase: f64,
+    line: DelayLine,
+    allpasses: [Allpass; PHASER_STAGES],
+    /// Last wet sample, for feedback.
+    wet: f64,
+}
+
+/// LFO driven modulation effects.
+#[derive(Debug)]
+pub struct Modulation {
+    pub(crate) kind: ModulationKind,
+    /// Of the LFO, in Hz.
+    pub(crate) rate: Expression,
+    /// 0..1
+    pub(crate) depth: Expression,
+    pub(crate) feedback: Expression,
+    /// 0 modulates every channel the same, 1 spreads the LFO phases over a whole cycle.
+    pub(crate) spread: Expression,
+    pub(crate) mix: Expression,
+
+    pub(crate) state: PerChannel<Option<ModulationState>>,
+}
+
+impl Modulation {
+    pub fn new(kind: ModulationKind) -> Self {
+        let (rate, mix) = match kind {
+            ModulationKind::Tremolo | ModulationKind::Vibrato => (5., 1.),
+            ModulationKind::Chorus => (0.8, 0.5),
+            ModulationKind::Flanger => (0.25, 0.5),
+            ModulationKind::Phaser => (0.5, 0.5),
+        };
+
+        Self {
+            kind,
+            rate: Expression::Lit(Number::Real(rate)),
+            depth: Expression::Lit(Number::Real(0.5)),
+            feedback: Expression::zero(),
+            spread: Expression::zero(),
+            mix: Expression::Lit(Number::Real(mix)),
+            state: PerChannel::default(),
+        }
+    }
+
+    /// Seconds until the feedback has faded by 60 dB.
+    pub fn tail(&self) -> f64 {
+        let (_, longest) = self.kind.delays();
+        longest * delay::echoes(&self.feedback)
+    }
+
+    pub fn apply(&mut self, v: f64, gi: GenInfo) -> Result<f64, ExpressionError> {
+        let rate = self.rate.evaluate(Some(gi))?;
+        let depth = self.depth.evaluate(Some(gi))?.clamp(0., 1.);
+        let feedback = self.feedback.evaluate(Some(gi))?.clamp(-0.99, 0.99);
+        let spread = self.spread.evaluate(Some(gi))?;
+        let mix = self.mix.evaluate(Some(gi))?;
+
+        let kind = self.kind;
+        let state = self.state.get(gi.channel).get_or_insert_with(|| {
+            let (_, longest) = kind.delays();
+
+            ModulationState {
+                phase: 0.,
+                line: DelayLine::new((longest * gi.samplerate as f64).ceil() as usize + 2),
+                allpasses: Default::default(),
+                wet: 0.,
+            }
+        });
+
+        let offset = spread * gi.channel as f64 / gi.channels.max(1) as f64;
+        let lfo = 0.5 - 0.5 * f64::cos(TAU * (state.phase + offset));
+        state.phase = (state.phase + rate / gi.samplerate as f64).fract();
+
+        let input = v + feedback * state.wet;
+        let wet = match kind {
+            ModulationKind::Tremolo => v * (1. - depth * lfo),
+
+            ModulationKind::Vibrato | ModulationKind::Chorus | ModulationKind::Flanger => {
+                let (shortest, longest) = kind.delays();
+                let delay = shortest + (longest - shortest) * depth * lfo;
+
+                state.line.write(input);
+                state.line.tap(delay * gi.samplerate as f64)
+            }
+
+            ModulationKind::Phaser => {
+                let freq = PHASER_CENTER * f64::powf(2., (lfo * 2. - 1.) * 2. * depth);
+                let tan = f64::tan(PI * freq / gi.samplerate as f64);
+                let a = (tan - 1.) / (tan + 1.);
+
+                let mut x = input;
+                for (x1, y1) in &mut state.allpasses {
+                    let y = a * x + *x1 - a * *y1;
+                    (*x1, *y1) = (x, y);
+                    x = y;
+                }
+                x
+            }
+        };
+        state.wet = wet;
+
+        Ok(dry_wet(v, wet, mix))
+    }
+}
+
+impl Display for Modulation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} (rate: {} Hz, depth: {}, feedback: {}, spread: {}, mix: {})",
+            self.kind, self.rate, self.depth, self.feedback, self.spread, self.mix
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::{Modulation, ModulationKind};
+    use crate::{
+        gen::tests::{render, rms, RATE},
+        parse::{tokenizer::Number, Expression},
+    };
+
+    #[test]
+    fn tremolo_dips_by_its_depth() {
+        for depth in [0.25, 0.6, 1.] {
+            // a steady 1 (the first half of a slow square), so only the tremolo moves
+            let x = render(&format!(
+                "\"t\" 0.5s on 1 square(1 hz, 0 rad, naive, :) on * @ 1 {{ tremolo(rate 10 hz, depth {depth}) : }}"
+            ));
+
+            let x = &x[RATE / 10..RATE * 4 / 10];
+            let max = x.iter().copied().fold(f64::MIN, f64::max);
+            let min = x.iter().copied().fold(f64::MAX, f64::min);
+
+            assert!((max - 1.).abs() < 1e-3, "depth {depth}: peaks at {max}");
+            assert!(
+                (min - (1. - depth)).abs() < 1e-3,
+                "depth {depth}: dips to {min}"
+            );
+        }
+    }
+
+    #[test]
+    fn vibrato_bends_by_its_depth() {
+        for depth in [0.5, 1.] {
+            let x = render(&format!(
+                "\"t\" 1s on 1 sine(1000 hz, 0 rad, :) on * @ 1 {{ vibrato(rate 5 hz, depth {depth}) : }}"
+            ));
+
+            // frequency of every cycle, from the rising zero crossings
+            let crossings: Vec<f64> = (RATE / 100..x.len())
+                .filter(|n| x[n - 1] < 0. && x[*n] >= 0.)
+                .map(|n| n as f64 - 1. + x[n - 1] / (x[n - 1] - x[n]))
+                .collect();
+            let freqs: Vec<f64> = crossings
+                .windows(2)
+                .map(|c| RATE as f64 / (c[1] - c[0]))
+                .collect();
+
+            // the delay sweeps 5 ms, the fastest it moves is 5 ms * depth * pi * rate
+            let bend = 1000. * 0.005 * depth * PI * 5.;
+            let highest = freqs.iter().copied().fold(f64::MIN, f64::max) - 1000.;
+            let lowest = 1000. - freqs.iter().copied().fold(f64::MAX, f64::min);
+
+            for dev in [highest, lowest] {
+                assert!(
+                    dev < bend * 1.02 && dev > bend * 0.95,
+                    "depth {depth}: bends {dev} Hz, not {bend}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn phaser_notch_follows_the_lfo() {
+        // the LFO is at its bottom at 0 s and its top at 1 s, the allpasses turn at 200 Hz
+        // and 800 Hz then, and the notch of 4 stages is at tan(pi / 8) of that
+        for (freq, notched, open) in [
+            (200. * f64::tan(PI / 8.), 0.05, 1.),
+            (800. * f64::tan(PI / 8.), 1., 0.05),
+        ] {
+            let x = render(&format!(
+                "\"t\" 1.2s on 1 sine({freq} hz, 0 rad, :) on * @ 1 {{ phaser(rate 0.5 hz, depth 0.5, mix 0.5) : }}"
+            ));
+
+            let level = |secs: f64| {
+                let at = (secs * RATE as f64) as usize;
+                rms(&x[at - RATE / 50..at + RATE / 50])
+            };
+            let depth = 20. * (level(notched) / level(open)).log10();
+            assert!(depth < -20., "{freq} Hz: notch only {depth:.1} dB deep");
+        }
+    }
+
+    #[test]
+    fn feedback_lengthens_the_tail() {
+        let mut chorus = Modulation::new(ModulationKind::Chorus);
+        assert_eq!(chorus.tail(), 0.03);
+
+        // 0.5 loses 6 dB a trip, 60 dB take 10 trips through the longest delay
+        chorus.feedback = Expression::Lit(Number::Real(0.5));
+        assert!((chorus.tail() - 0.3).abs() < 0.01, "{}", chorus.tail());
+    }
+}
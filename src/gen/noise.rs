@@ -0,0 +1,198 @@
+use std::fmt::Display;
+
+use super::{GenInfo, PerChannel};
+
+/// xorshift64*, seeded through splitmix64.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+
+        // xorshift gets stuck on 0
+        Self(if z == 0 { 1 } else { z })
+    }
+
+    pub fn for_channel(seed: u64, channel: usize) -> Self {
+        Self::new(seed ^ Self::new(channel as u64).0)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform in 0..1.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform in -1..1.
+    pub fn next_bipolar(&mut self) -> f64 {
+        self.next_f64() * 2. - 1.
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoiseColor {
+    White,
+    Pink,
+    Brown,
+    Blue,
+}
+
+impl NoiseColor {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "white" => Self::White,
+            "pink" => Self::Pink,
+            "brown" | "red" => Self::Brown,
+            "blue" => Self::Blue,
+
+            _ => return None,
+        })
+    }
+}
+
+impl Display for NoiseColor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NoiseColor::White => write!(f, "white"),
+            NoiseColor::Pink => write!(f, "pink"),
+            NoiseColor::Brown => write!(f, "brown"),
+            NoiseColor::Blue => write!(f, "blue"),
+        }
+    }
+}
+
+const PINK_ROWS: usize = 16;
+
+#[derive(Debug, Default)]
+pub struct NoiseState {
+    rng: Option<Rng>,
+
+    // Voss-McCartney
+    counter: u32,
+    rows: [f64; PINK_ROWS],
+    rows_sum: f64,
+    last_pink: f64,
+
+    brown: f64,
+}
+
+impl NoiseState {
+    fn pink(&mut self, rng: &mut Rng) -> f64 {
+        // every row is updated half as often as the previous one
+        self.counter = self.counter.wrapping_add(1);
+        let row = self.counter.trailing_zeros() as usize;
+        if row < PINK_ROWS {
+            let new = rng.next_bipolar();
+            self.rows_sum += new - self.rows[row];
+            self.rows[row] = new;
+        }
+
+        // the rows and one white sample, each -1..1
+        (self.rows_sum + rng.next_bipolar()) / (PINK_ROWS + 1) as f64
+    }
+}
+
+#[derive(Debug)]
+pub struct NoiseSource {
+    pub(crate) color: NoiseColor,
+    pub(crate) seed: u64,
+
+    pub(crate) state: PerChannel<NoiseState>,
+}
+
+impl NoiseSource {
+    pub fn new(color: NoiseColor, seed: u64) -> Self {
+        Self {
+            color,
+            seed,
+            state: PerChannel::default(),
+        }
+    }
+
+    pub fn gen(&mut self, gi: GenInfo) -> f64 {
+        let state = self.state.get(gi.channel);
+        let mut rng = state
+            .rng
+            .take()
+            .unwrap_or_else(|| Rng::for_channel(self.seed, gi.channel));
+
+        let v = match self.color {
+            NoiseColor::White => rng.next_bipolar(),
+            NoiseColor::Pink => state.pink(&mut rng),
+
+            // leaky integrated white noise, the gain brings it to about pink's level but the
+            // integrator can reach ±1 on its own
+            NoiseColor::Brown => {
+                state.brown = (state.brown + 0.02 * rng.next_bipolar()) / 1.02;
+                (state.brown * 3.5).clamp(-1., 1.)
+            }
+
+            // differentiated pink noise
+            NoiseColor::Blue => {
+                let pink = state.pink(&mut rng);
+                let v = pink - state.last_pink;
+                state.last_pink = pink;
+                (v * 2.).clamp(-1., 1.)
+            }
+        };
+
+        state.rng = Some(rng);
+        v
+    }
+}
+
+impl Display for NoiseSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} noise (seed: {})", self.color, self.seed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gen::tests::{render_channels, rms};
+
+    #[test]
+    fn seeded_noise_repeats_and_keeps_its_level() {
+        // rms levels, white is uniform in -1..1
+        for (color, level) in [
+            ("white", 1. / 3f64.sqrt()),
+            ("pink", 0.14),
+            ("brown", 0.2),
+            ("blue", 0.14),
+        ] {
+            let src = format!("\"t\" 2s on 2 noise({color}, seed 42, :) on * @ 1 {{}}");
+
+            let a = render_channels(&src);
+            let b = render_channels(&src);
+            assert!(a == b, "{color}: same seed, different noise");
+
+            let (l, r) = (&a[0], &a[1]);
+            let dot = |x: &[f64], y: &[f64]| x.iter().zip(y).map(|(x, y)| x * y).sum::<f64>();
+            let correlation = dot(l, r) / (dot(l, l) * dot(r, r)).sqrt();
+            assert!(
+                correlation.abs() < 0.1,
+                "{color}: correlation {correlation}"
+            );
+
+            let peak = l.iter().chain(r).fold(0f64, |p, v| p.max(v.abs()));
+            assert!(peak <= 1., "{color}: peak {peak}");
+            for c in [l, r] {
+                let rms = rms(c);
+                assert!(
+                    (rms / level - 1.).abs() < 0.2,
+                    "{color}: rms {rms}, not about {level}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use std::{f64::consts::FRAC_PI_2, fmt::Display};
+
+use crate::parse::{Expression, ExpressionError};
+
+use super::GenInfo;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PanLaw {
+    /// -3 dB in the middle, the power stays the same.
+    #[default]
+    ConstantPower,
+    /// -4.5 dB in the middle, between the other two.
+    Compromise,
+    /// -6 dB in the middle, the amplitude stays the same.
+    Linear,
+}
+
+impl PanLaw {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "power" | "constant_power" => Self::ConstantPower,
+            "compromise" => Self::Compromise,
+            "linear" => Self::Linear,
+
+            _ => return None,
+        })
+    }
+
+    /// Gain of a speaker `distance` speakers away from the source, 0..1.
+    fn gain(self, distance: f64) -> f64 {
+        if distance >= 1. {
+            return 0.;
+        }
+
+        let power = f64::cos(distance * FRAC_PI_2);
+        let linear = 1. - distance;
+
+        match self {
+            Self::ConstantPower => power,
+            Self::Compromise => (power * linear).sqrt(),
+            Self::Linear => linear,
+        }
+    }
+}
+
+impl Display for PanLaw {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PanLaw::ConstantPower => write!(f, "-3 dB"),
+            PanLaw::Compromise => write!(f, "-4.5 dB"),
+            PanLaw::Linear => write!(f, "-6 dB"),
+        }
+    }
+}
+
+/// Where channel `c` of `channels` sits, from -1 (the first) to 1 (the last).
+fn position(c: usize, channels: usize) -> f64 {
+    match channels {
+        0 | 1 => 0.,
+        n => c as f64 / (n - 1) as f64 * 2. - 1.,
+    }
+}
+
+#[derive(Debug)]
+pub struct Pan {
+    /// -1..1
+    pub(crate) position: Expression,
+    pub(crate) law: PanLaw,
+}
+
+impl Pan {
+    pub fn apply(&self, v: f64, gi: GenInfo) -> Result<f64, ExpressionError> {
+        if gi.channels <= 1 {
+            return Ok(v);
+        }
+
+        let pan = self.position.evaluate(Some(gi))?.clamp(-1., 1.);
+        let spacing = 2. / (gi.channels - 1) as f64;
+        let distance = (pan - position(gi.channel, gi.channels)).abs() / spacing;
+
+        Ok(v * self.law.gain(distance))
+    }
+}
+
+impl Display for Pan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pan {} (law: {})", self.position, self.law)
+    }
+}
+
+/// Turns down the channels on the other side, never up.
+#[derive(Debug)]
+pub struct Balance {
+    /// -1..1
+    pub(crate) balance: Expression,
+}
+
+impl Balance {
+    pub fn apply(&self, v: f64, gi: GenInfo) -> Result<f64, ExpressionError> {
+        let balance = self.balance.evaluate(Some(gi))?.clamp(-1., 1.);
+        let gain = 1. + balance * position(gi.channel, gi.channels);
+
+        Ok(v * gain.clamp(0., 1.))
+    }
+}
+
+impl Display for Balance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "balance {}", self.balance)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct WidthState {
+    /// When the current frame started, in song seconds.
+    frame: f64,
+    /// In seconds.
+    latency: f64,
+    current: Vec<Option<f64>>,
+    previous: Vec<Option<f64>>,
+}
+
+/// Scales every channel's difference from the mean of all of them. That needs the whole
+/// frame, so the output is a frame late.
+#[derive(Debug)]
+pub struct Width {
+    /// 0 is mono, 1 leaves the signal alone, above 1 widens.
+    pub(crate) width: Expression,
+
+    pub(crate) state: WidthState,
+}
+
+impl Width {
+    pub fn new(width: Expression) -> Self {
+        Self {
+            width,
+            state: WidthState::default(),
+        }
+    }
+
+    pub fn tail(&self) -> f64 {
+        self.state.latency
+    }
+
+    pub fn apply(&mut self, v: f64, gi: GenInfo) -> Result<f64, ExpressionError> {
+        let width = self.width.evaluate(Some(gi))?;
+
+        let channels = gi.channels.max(gi.channel + 1);
+        let state = &mut self.state;
+        state.latency = 1. / gi.samplerate as f64;
+        if state.current.len() < channels {
+            state.current.resize(channels, None);
+            state.previous.resize(channels, None);
+        }
+
+        if gi.song_secs != state.frame {
+            state.frame = gi.song_secs;
+            std::mem::swap(&mut state.current, &mut state.previous);
+            state.current.fill(None);
+        }
+        state.current[gi.channel] = Some(v);
+
+        let Some(v) = state.previous[gi.channel] else {
+            return Ok(0.);
+        };
+        let (sum, n) = state
+            .previous
+            .iter()
+            .flatten()
+            .fold((0., 0), |(sum, n), v| (sum + v, n + 1));
+        let mid = sum / n as f64;
+
+        Ok(mid + (v - mid) * width)
+    }
+}
+
+impl Display for Width {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "width {}", self.width)
+    }
+}
+
+/// A gain for every channel, channels without one are silent.
+#[derive(Debug)]
+pub struct Gains {
+    pub(crate) gains: Vec<Expression>,
+}
+
+impl Gains {
+    pub fn apply(&self, v: f64, gi: GenInfo) -> Result<f64, ExpressionError> {
+        match self.gains.get(gi.channel) {
+            Some(gain) => Ok(v * gain.evaluate(Some(gi))?),
+            None => Ok(0.),
+        }
+    }
+}
+
+impl Display for Gains {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "gains [")?;
+        for (i, g) in self.gains.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{g}")?;
+        }
+        write!(f, "]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        gen::{
+            dynamics::gain_to_db,
+            tests::{render_channels, RATE},
+        },
+        parse::{get_song, ParserError},
+    };
+
+    /// A steady 1 (the first half of a slow square) on both channels, through `effects`.
+    fn steady(len: &str, effects: &str) -> Vec<Vec<f64>> {
+        render_channels(&format!(
+            "\"t\" 0.1s on 2 square(1 hz, 0 rad, naive, {len}) on * @ 1 {{ {effects} }}"
+        ))
+    }
+
+    #[test]
+    fn pan_laws_at_the_centre() {
+        for (law, expected) in [("power", -3.01), ("compromise", -4.52), ("linear", -6.02)] {
+            let x = steady(":", &format!("pan(0, law {law}) :"));
+
+            for c in &x {
+                let gain = gain_to_db(c[RATE / 20]);
+                assert!((gain - expected).abs() < 0.01, "{law}: {gain:.2} dB");
+            }
+        }
+    }
+
+    #[test]
+    fn width_is_a_frame_late_but_keeps_every_frame() {
+        let frames = |x: &[Vec<f64>], v: f64| -> Vec<usize> {
+            (0..x[0].len())
+                .filter(|i| (x[0][*i] - v).abs() < 1e-9)
+                .collect()
+        };
+
+        // all on the left, then mono
+        let panned = frames(&steady(":0.05s", "pan(-1) :"), 1.);
+        let narrowed = frames(&steady(":0.05s", "pan(-1) : width(0) :"), 0.5);
+
+        let late: Vec<usize> = panned.iter().map(|i| i + 1).collect();
+        assert_eq!(narrowed, late);
+    }
+
+    #[test]
+    fn panning_needs_every_channel() {
+        for effect in ["pan(0)", "balance(0.5)", "width(0)", "gains([1, 1])"] {
+            let src = format!("\"t\" 1s on 2 sine(200 hz, 0 rad, :) on 0 @ 1 {{ {effect} : }}");
+            assert!(
+                matches!(get_song("test", &src), Err(ParserError::NotAllChannels)),
+                "{effect}"
+            );
+        }
+    }
+}
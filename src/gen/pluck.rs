@@ -0,0 +1,156 @@
+use std::fmt::Display;
+
+use crate::parse::{Expression, ExpressionError};
+
+use super::{noise::Rng, GenInfo, PerChannel};
+
+/// Karplus-Strong.
+#[derive(Debug)]
+pub struct PluckSource {
+    pub(crate) freq: Expression,
+    /// Seconds to fall by 60 dB.
+    pub(crate) decay: f64,
+    /// 0..1
+    pub(crate) brightness: Expression,
+    pub(crate) seed: u64,
+
+    pub(crate) strings: PerChannel<StringState>,
+}
+
+#[derive(Debug, Default)]
+pub struct StringState {
+    line: Vec<f64>,
+    pos: usize,
+
+    /// Of the feedback lowpass, `(1 - stretch) x[n] + stretch x[n - 1]`.
+    stretch: f64,
+    last: f64,
+
+    // tunes the fractional part of the period
+    coefficient: f64,
+    ap_in: f64,
+    ap_out: f64,
+
+    gain: f64,
+}
+
+impl StringState {
+    fn pluck(&mut self, samplerate: f64, freq: f64, decay: f64, brightness: f64, mut rng: Rng) {
+        let period = (samplerate / freq.max(1.)).max(2.);
+
+        // the lowpass delays by `stretch` samples and the allpass by 0.1..1.1
+        self.stretch = 0.5 * (1. - brightness);
+        let len = ((period - self.stretch - 0.1).floor() as usize).max(1);
+        let frac = period - self.stretch - len as f64;
+        self.coefficient = (1. - frac) / (1. + frac);
+
+        self.gain = f64::powf(0.001, 1. / (freq.abs() * decay).max(f64::EPSILON));
+
+        // darker plucks get a smoother burst
+        let smoothing = 0.1 + 0.9 * brightness;
+        let mut y = 0.;
+        self.line = (0..len)
+            .map(|_| {
+                y += smoothing * (rng.next_bipolar() - y);
+                y
+            })
+            .collect();
+
+        let mean = self.line.iter().sum::<f64>() / len as f64;
+        let peak = self
+            .line
+            .iter()
+            .fold(0f64, |p, v| p.max((v - mean).abs()))
+            .max(f64::EPSILON);
+        for v in &mut self.line {
+            *v = (*v - mean) / peak;
+        }
+    }
+
+    fn next(&mut self) -> f64 {
+        let x = self.line[self.pos];
+
+        let lowpassed = (1. - self.stretch) * x + self.stretch * self.last;
+        self.last = x;
+
+        let y = self.coefficient * (lowpassed - self.ap_out) + self.ap_in;
+        self.ap_in = lowpassed;
+        self.ap_out = y;
+
+        self.line[self.pos] = y * self.gain;
+        self.pos = (self.pos + 1) % self.line.len();
+
+        x
+    }
+}
+
+impl PluckSource {
+    pub fn new(freq: Expression, decay: f64, brightness: Expression, seed: u64) -> Self {
+        Self {
+            freq,
+            decay,
+            brightness,
+            seed,
+            strings: PerChannel::default(),
+        }
+    }
+
+    pub fn gen(&mut self, gi: GenInfo) -> Result<f64, ExpressionError> {
+        let string = self.strings.get(gi.channel);
+
+        // frequency and brightness are fixed once the string is plucked
+        if string.line.is_empty() {
+            let freq = self.freq.evaluate(Some(gi))?;
+            let brightness = self.brightness.evaluate(Some(gi))?.clamp(0., 1.);
+
+            string.pluck(
+                gi.samplerate as f64,
+                freq,
+                self.decay,
+                brightness,
+                Rng::for_channel(self.seed, gi.channel),
+            );
+        }
+
+        Ok(string.next())
+    }
+}
+
+impl Display for PluckSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} Hz pluck (decay: {}s, brightness: {}, seed: {})",
+            self.freq, self.decay, self.brightness, self.seed
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gen::tests::{render, rms, RATE};
+
+    #[test]
+    fn string_rings_at_its_pitch_and_decays_in_time() {
+        // a full brightness string only loses what the decay asks for
+        let x = render("\"t\" 1s on 1 pluck(441 hz, 0.5s, 1, :) on * @ 1 {}");
+
+        let window = &x[RATE / 10..RATE / 5];
+        let correlation = |lag: usize| -> f64 {
+            window
+                .iter()
+                .zip(&x[RATE / 10 + lag..])
+                .map(|(a, b)| a * b)
+                .sum()
+        };
+        let period = (80..120).max_by(|a, b| correlation(*a).total_cmp(&correlation(*b)));
+        assert_eq!(period, Some(100));
+
+        let at = |secs: f64| {
+            let start = (secs * RATE as f64) as usize;
+            rms(&x[start..start + RATE / 20])
+        };
+        let drop = 20. * f64::log10(at(0.5) / at(0.));
+        assert!((drop + 60.).abs() < 3., "{drop:.1} dB after the decay time");
+    }
+}
@@ -0,0 +1,210 @@
+use std::fmt::Display;
+
+use crate::parse::{tokenizer::Number, Expression, ExpressionError};
+
+use super::{delay::DelayLine, dry_wet, GenInfo, PerChannel};
+
+/// Freeverb's tunings, in samples at 44.1 kHz.
+const COMBS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASSES: [usize; 4] = [556, 441, 341, 225];
+/// Added to every delay of odd channels, so the channels decorrelate.
+const STEREO_SPREAD: usize = 23;
+
+const INPUT_GAIN: f64 = 0.015;
+/// Size that isn't a constant is assumed to be this big when working out the tail.
+const VARIABLE_SIZE: f64 = 1.;
+
+fn room_feedback(size: f64) -> f64 {
+    size.clamp(0., 1.) * 0.28 + 0.7
+}
+
+#[derive(Debug)]
+struct Comb {
+    line: DelayLine,
+    filter: f64,
+}
+
+impl Comb {
+    fn process(&mut self, x: f64, feedback: f64, damping: f64) -> f64 {
+        let y = self.line.read();
+        self.filter = y * (1. - damping) + self.filter * damping;
+        self.line.write(x + self.filter * feedback);
+        y
+    }
+}
+
+#[derive(Debug)]
+struct Allpass {
+    line: DelayLine,
+}
+
+impl Allpass {
+    fn process(&mut self, x: f64) -> f64 {
+        let delayed = self.line.read();
+        self.line.write(x + delayed * 0.5);
+        delayed - x
+    }
+}
+
+#[derive(Debug)]
+pub struct ReverbState {
+    pre_delay: DelayLine,
+    combs: Vec<Comb>,
+    allpasses: Vec<Allpass>,
+    wet: f64,
+}
+
+impl ReverbState {
+    fn new(channel: usize, samplerate: usize, pre_delay: f64) -> Self {
+        let spread = if channel % 2 == 1 { STEREO_SPREAD } else { 0 };
+        let scale = |len: usize| (len + spread) * samplerate / 44100;
+
+        Self {
+            pre_delay: DelayLine::new((pre_delay * samplerate as f64).round() as usize),
+            combs: COMBS
+                .iter()
+                .map(|len| Comb {
+                    line: DelayLine::new(scale(*len)),
+                    filter: 0.,
+                })
+                .collect(),
+            allpasses: ALLPASSES
+                .iter()
+                .map(|len| Allpass {
+                    line: DelayLine::new(scale(*len)),
+                })
+                .collect(),
+            wet: 0.,
+        }
+    }
+}
+
+/// After Freeverb.
+#[derive(Debug)]
+pub struct Reverb {
+    /// 0..1
+    pub(crate) size: Expression,
+    /// 0..1
+    pub(crate) damping: Expression,
+    /// In seconds.
+    pub(crate) pre_delay: f64,
+    /// 0 is mono, 1 is the full stereo image.
+    pub(crate) width: Expression,
+    pub(crate) mix: Expression,
+
+    pub(crate) state: PerChannel<Option<ReverbState>>,
+}
+
+impl Default for Reverb {
+    fn default() -> Self {
+        Self {
+            size: Expression::Lit(Number::Real(0.5)),
+            damping: Expression::Lit(Number::Real(0.5)),
+            pre_delay: 0.,
+            width: Expression::Lit(Number::Integer(1)),
+            mix: Expression::Lit(Number::Real(0.3)),
+            state: PerChannel::default(),
+        }
+    }
+}
+
+impl Reverb {
+    /// Seconds until the reverb has faded by 60 dB.
+    pub fn tail(&self) -> f64 {
+        let size = self.size.evaluate(None).unwrap_or(VARIABLE_SIZE);
+        let longest = (COMBS[COMBS.len() - 1] + STEREO_SPREAD) as f64 / 44100.;
+
+        self.pre_delay + longest * f64::ln(0.001) / room_feedback(size).ln()
+    }
+
+    pub fn apply(&mut self, v: f64, gi: GenInfo) -> Result<f64, ExpressionError> {
+        let feedback = room_feedback(self.size.evaluate(Some(gi))?);
+        let damping = self.damping.evaluate(Some(gi))?.clamp(0., 1.) * 0.4;
+        let width = self.width.evaluate(Some(gi))?.clamp(0., 1.);
+        let mix = self.mix.evaluate(Some(gi))?;
+
+        let pre_delay = self.pre_delay;
+        let state = self
+            .state
+            .get(gi.channel)
+            .get_or_insert_with(|| ReverbState::new(gi.channel, gi.samplerate, pre_delay));
+
+        let input = state.pre_delay.read() * INPUT_GAIN;
+        state.pre_delay.write(v);
+
+        let mut wet: f64 = state
+            .combs
+            .iter_mut()
+            .map(|c| c.process(input, feedback, damping))
+            .sum();
+        for a in &mut state.allpasses {
+            wet = a.process(wet);
+        }
+        state.wet = wet;
+
+        // narrows the image by mixing in what the other channels reverberated last
+        let (mut others, mut n) = (0., 0);
+        for c in (0..gi.channels).filter(|c| *c != gi.channel) {
+            if let Some(s) = self.state.get(c) {
+                others += s.wet;
+                n += 1;
+            }
+        }
+        let wet = match n {
+            0 => wet,
+            n => wet * (0.5 + width / 2.) + others / n as f64 * (0.5 - width / 2.),
+        };
+
+        Ok(dry_wet(v, wet, mix))
+    }
+}
+
+impl Display for Reverb {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "reverb (size: {}, damping: {}, pre-delay: {}s, width: {}, mix: {})",
+            self.size, self.damping, self.pre_delay, self.width, self.mix
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        gen::{
+            tests::{render, rms, RATE},
+            Reverb,
+        },
+        parse::{tokenizer::Number, Expression},
+    };
+
+    #[test]
+    fn tail_covers_the_fade_to_60_db() {
+        let reverb = Reverb {
+            size: Expression::Lit(Number::Real(0.7)),
+            damping: Expression::zero(),
+            ..Default::default()
+        };
+        let tail = reverb.tail();
+
+        let x = render(&format!(
+            "\"t\" {}s on 1 impulse(0, :0.01s) on * @ 1 {{ reverb(size 0.7, damping 0, mix 1) : }}",
+            tail + 1.
+        ));
+        let level = |secs: f64| {
+            let start = (secs * RATE as f64) as usize;
+            20. * rms(&x[start..start + RATE / 10]).log10()
+        };
+
+        // the tail is worked out from the longest comb, the others die out sooner
+        let end = level(tail) - level(0.1);
+        assert!(end < -60., "{end:.1} dB after {tail:.2}s");
+
+        let before = level(tail * 0.7) - level(0.1);
+        assert!(
+            before > -60.,
+            "{before:.1} dB well before the end of the tail"
+        );
+    }
+}
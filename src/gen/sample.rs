@@ -0,0 +1,186 @@
+use std::fmt::Display;
+
+use crate::{
+    parse::{Expression, ExpressionError},
+    wav::reader::Wave,
+};
+
+use super::{GenInfo, PerChannel};
+
+/// Plays back an audio file.
+#[derive(Debug)]
+pub struct SampleSource {
+    pub(crate) path: String,
+    pub(crate) wave: Wave,
+
+    /// Playback speed, 1 is the original speed.
+    pub(crate) rate: Option<Expression>,
+    /// Transposition in semitones, on top of `rate`.
+    pub(crate) pitch: Option<Expression>,
+
+    pub(crate) looping: bool,
+
+    /// Offsets into the file in seconds.
+    pub(crate) from: f64,
+    pub(crate) to: Option<f64>,
+
+    pub(crate) playheads: PerChannel<Playhead>,
+}
+
+#[derive(Debug, Default)]
+pub struct Playhead {
+    /// Seconds of the file played since `from`.
+    pos: f64,
+    last_secs: Option<f64>,
+}
+
+impl SampleSource {
+    pub fn new(path: String, wave: Wave) -> Self {
+        Self {
+            path,
+            wave,
+            rate: None,
+            pitch: None,
+            looping: false,
+            from: 0.,
+            to: None,
+            playheads: PerChannel::default(),
+        }
+    }
+
+    pub fn file_length(&self) -> f64 {
+        self.wave.frames() as f64 / self.wave.desc.samplerate() as f64
+    }
+
+    pub fn gen(&mut self, gi: GenInfo) -> Result<f64, ExpressionError> {
+        let mut speed = 1.;
+        if let Some(rate) = &self.rate {
+            speed *= rate.evaluate(Some(gi))?;
+        }
+        if let Some(pitch) = &self.pitch {
+            speed *= f64::powf(2., pitch.evaluate(Some(gi))? / 12.);
+        }
+
+        let from = self.from.min(self.file_length());
+        let to = self.to.unwrap_or(f64::INFINITY).min(self.file_length());
+        let len = to - from;
+
+        // the position is integrated so changing rates don't jump around in the file
+        let secs = gi.secs();
+        let playhead = self.playheads.get(gi.channel);
+        if let Some(last) = playhead.last_secs {
+            playhead.pos += (secs - last) * speed;
+        }
+        playhead.last_secs = Some(secs);
+
+        let mut pos = playhead.pos;
+        if self.looping && len > 0. {
+            pos = pos.rem_euclid(len);
+        } else if !(0. ..len).contains(&pos) {
+            return Ok(0.);
+        }
+
+        let frame = (from + pos) * self.wave.desc.samplerate() as f64;
+        let channel = gi.channel % self.wave.desc.channels() as usize;
+
+        Ok(interpolate(&self.wave, frame, channel))
+    }
+}
+
+impl Display for SampleSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "sample \"{}\"", self.path)?;
+
+        if let Some(rate) = &self.rate {
+            write!(f, " (rate: {rate})")?;
+        }
+        if let Some(pitch) = &self.pitch {
+            write!(f, " (pitch: {pitch})")?;
+        }
+
+        write!(f, " {}s:", self.from)?;
+        if let Some(to) = self.to {
+            write!(f, "{to}s")?;
+        }
+
+        if self.looping {
+            write!(f, " looping")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads `channel` of `wave` at a fractional frame, with 4 point Hermite interpolation.
+pub fn interpolate(wave: &Wave, frame: f64, channel: usize) -> f64 {
+    let i = frame.floor() as isize;
+    let x = frame - i as f64;
+
+    let y = |i: isize| {
+        if (0..wave.frames() as isize).contains(&i) {
+            wave.get(i as usize, channel)
+        } else {
+            0.
+        }
+    };
+
+    let (y0, y1, y2, y3) = (y(i - 1), y(i), y(i + 1), y(i + 2));
+
+    let c1 = 0.5 * (y2 - y0);
+    let c2 = y0 - 2.5 * y1 + 2. * y2 - 0.5 * y3;
+    let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+
+    ((c3 * x + c2) * x + c1) * x + y1
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gen::tests::{render, write_wave, RATE};
+
+    #[test]
+    fn rate_and_looping_move_through_the_file() {
+        // one second ramp, every sample holds its own time
+        let ramp: Vec<f64> = (0..100).map(|i| i as f64 / 100.).collect();
+        let path = write_wave("sample-ramp", 100, &ramp);
+
+        for looping in [false, true] {
+            let opts = if looping { "loop," } else { "" };
+            let x = render(&format!(
+                "\"t\" 1s on 1 sample({path:?}, rate 2, {opts} :) on * @ 1 {{}}"
+            ));
+
+            let at = |secs: f64| x[(secs * RATE as f64) as usize];
+            assert!((at(0.2) - 0.4).abs() < 1e-3, "{looping}: {}", at(0.2));
+
+            let after_end = if looping { 0.4 } else { 0. };
+            assert!((at(0.7) - after_end).abs() < 1e-3, "{looping}: {}", at(0.7));
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn from_and_to_cut_the_file_and_set_the_loop() {
+        let ramp: Vec<f64> = (0..100).map(|i| i as f64 / 100.).collect();
+        let path = write_wave("sample-cut", 100, &ramp);
+
+        for looping in [false, true] {
+            let opts = if looping { "loop," } else { "" };
+            let x = render(&format!(
+                "\"t\" 1s on 1 sample({path:?}, from 0.2s, to 0.6s, {opts} :) on * @ 1 {{}}"
+            ));
+            let at = |secs: f64| x[(secs * RATE as f64) as usize];
+
+            // the first and last samples of the cut
+            assert!((x[0] - 0.2).abs() < 1e-9, "{looping}: starts at {}", x[0]);
+            assert!((at(0.399) - 0.599).abs() < 1e-3, "{looping}: {}", at(0.399));
+
+            // back at the start of the cut, or silent
+            let (after, later) = if looping { (0.21, 0.25) } else { (0., 0.) };
+            assert!((at(0.41) - after).abs() < 1e-3, "{looping}: {}", at(0.41));
+            assert!((at(0.85) - later).abs() < 1e-3, "{looping}: {}", at(0.85));
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,244 @@
+use std::{f64::consts::TAU, fmt::Display};
+
+use crate::parse::{Expression, ExpressionError};
+
+use super::{GenInfo, PerChannel};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SweepCurve {
+    Linear,
+    /// Exponential, equal time per octave.
+    Log,
+}
+
+impl SweepCurve {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "log" | "exp" => Self::Log,
+            "lin" | "linear" => Self::Linear,
+
+            _ => return None,
+        })
+    }
+
+    /// Cycles done `t` seconds into a `len` seconds long sweep from `f0` to `f1` Hz.
+    fn cycles(self, f0: f64, f1: f64, len: f64, t: f64) -> f64 {
+        // a log sweep needs both ends on the same side of 0, and neither at 0
+        let ratio = f1 / f0;
+        if self == Self::Log && ratio > 0. && ratio.is_finite() && ratio != 1. {
+            let k = ratio.ln() / len;
+            f0 * (f64::exp(k * t) - 1.) / k
+        } else {
+            f0 * t + (f1 - f0) * t * t / (2. * len)
+        }
+    }
+}
+
+/// A sine sweeping from `from` to `to` over the length of the source.
+///
+/// The phase is the closed form integral of the frequency, so it stays exact over long sweeps.
+#[derive(Debug)]
+pub struct SweepSource {
+    pub(crate) from: Expression,
+    pub(crate) to: Expression,
+    pub(crate) curve: SweepCurve,
+}
+
+impl SweepSource {
+    pub fn gen(&mut self, gi: GenInfo) -> Result<f64, ExpressionError> {
+        let f0 = self.from.evaluate(Some(gi))?;
+        let f1 = self.to.evaluate(Some(gi))?;
+
+        let cycles = self.curve.cycles(f0, f1, gi.len_s, gi.secs());
+
+        Ok(f64::sin(TAU * cycles.fract()))
+    }
+}
+
+impl Display for SweepSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let curve = match self.curve {
+            SweepCurve::Linear => "linear",
+            SweepCurve::Log => "log",
+        };
+
+        write!(f, "{curve} sweep {} Hz -> {} Hz", self.from, self.to)
+    }
+}
+
+/// Single sample impulses every `period` seconds, or just one if it's 0.
+#[derive(Debug)]
+pub struct ImpulseSource {
+    pub(crate) period: f64,
+
+    /// Samples since the source started.
+    pub(crate) counters: PerChannel<u64>,
+}
+
+impl ImpulseSource {
+    pub fn new(period: f64) -> Self {
+        Self {
+            period,
+            counters: PerChannel::default(),
+        }
+    }
+
+    pub fn gen(&mut self, gi: GenInfo) -> f64 {
+        // whole samples so the impulses don't jitter
+        let period = (self.period * gi.samplerate as f64).round() as u64;
+
+        let counter = self.counters.get(gi.channel);
+        let n = *counter;
+        *counter += 1;
+
+        let hit = match period {
+            0 => n == 0,
+            p => n.is_multiple_of(p),
+        };
+
+        if hit {
+            1.
+        } else {
+            0.
+        }
+    }
+}
+
+impl Display for ImpulseSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.period > 0. {
+            write!(f, "impulses every {}s", self.period)
+        } else {
+            write!(f, "impulse")
+        }
+    }
+}
+
+/// Row and column frequencies of a DTMF key.
+pub fn dtmf_tones(key: char) -> Option<(f64, f64)> {
+    const ROWS: [f64; 4] = [697., 770., 852., 941.];
+    const COLUMNS: [f64; 4] = [1209., 1336., 1477., 1633.];
+    const KEYS: [&str; 4] = ["123A", "456B", "789C", "*0#D"];
+
+    let key = key.to_ascii_uppercase();
+    KEYS.iter()
+        .enumerate()
+        .find_map(|(row, keys)| keys.find(key).map(|column| (ROWS[row], COLUMNS[column])))
+}
+
+/// Dials `keys`, every key sounds for `tone` seconds followed by `gap` seconds of silence.
+#[derive(Debug)]
+pub struct DtmfSource {
+    pub(crate) keys: String,
+    pub(crate) tone: f64,
+    pub(crate) gap: f64,
+}
+
+impl DtmfSource {
+    pub fn new(keys: String) -> Self {
+        Self {
+            keys,
+            tone: 0.1,
+            gap: 0.1,
+        }
+    }
+
+    pub fn gen(&mut self, gi: GenInfo) -> f64 {
+        let secs = gi.secs();
+        let slot = self.tone + self.gap;
+
+        let index = (secs / slot).floor() as usize;
+        let t = secs - index as f64 * slot;
+
+        match self.keys.chars().nth(index).and_then(dtmf_tones) {
+            Some((low, high)) if t < self.tone => {
+                0.5 * (f64::sin(TAU * low * t) + f64::sin(TAU * high * t))
+            }
+
+            _ => 0.,
+        }
+    }
+}
+
+impl Display for DtmfSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "dtmf \"{}\" (tone: {}s, gap: {}s)",
+            self.keys, self.tone, self.gap
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dtmf_tones, SweepCurve};
+    use crate::{
+        gen::tests::{power, render, RATE},
+        parse::{get_song, ParserError},
+    };
+
+    #[test]
+    fn sweeps_start_and_end_at_their_frequencies() {
+        const H: f64 = 1e-6;
+
+        for curve in [SweepCurve::Linear, SweepCurve::Log] {
+            for (f0, f1) in [(100., 1600.), (1600., 100.), (0., 1000.), (1000., 0.)] {
+                let freq =
+                    |t: f64| (curve.cycles(f0, f1, 2., t + H) - curve.cycles(f0, f1, 2., t)) / H;
+
+                let (start, end) = (freq(0.), freq(2. - H));
+                assert!(
+                    (start - f0).abs() < 0.01,
+                    "{curve:?} {f0} -> {f1}: starts at {start}"
+                );
+                assert!(
+                    (end - f1).abs() < 0.01,
+                    "{curve:?} {f0} -> {f1}: ends at {end}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn dtmf_keys_are_their_two_tones() {
+        const TONES: [f64; 8] = [697., 770., 852., 941., 1209., 1336., 1477., 1633.];
+
+        for key in "123A456B789C*0#D".chars() {
+            let x = render(&format!(
+                "\"t\" 0.1s on 1 dtmf(\"{key}\", tone 100ms, gap 0ms, :) on * @ 1 {{}}"
+            ));
+            let (low, high) = dtmf_tones(key).unwrap();
+
+            let quietest = power(&x, low).min(power(&x, high));
+            for f in TONES.into_iter().filter(|f| *f != low && *f != high) {
+                let ratio = 10. * (power(&x, f) / quietest).log10();
+                assert!(ratio < -20., "{key}: {f} Hz at {ratio:.1} dB");
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_dtmf_keys_are_rejected() {
+        let src = "\"t\" 1s on 1 dtmf(\"12x\", :) on * @ 1 {}";
+        assert!(matches!(
+            get_song("test", src),
+            Err(ParserError::InvalidDtmfKey('x'))
+        ));
+    }
+
+    #[test]
+    fn impulses_are_single_samples() {
+        let x = render("\"t\" 1s on 1 impulse(0, 0.25s:) on * @ 1 {}");
+        for (n, v) in x.iter().enumerate() {
+            let expected = if n == RATE / 4 { 1. } else { 0. };
+            assert_eq!(*v, expected, "sample {n}");
+        }
+
+        let x = render("\"t\" 1s on 1 impulse(100ms, :) on * @ 1 {}");
+        for (n, v) in x.iter().enumerate() {
+            let expected = if n % (RATE / 10) == 0 { 1. } else { 0. };
+            assert_eq!(*v, expected, "sample {n}");
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use std::fmt::Display;
+
+use crate::{
+    fft::{self, Complex},
+    parse::{Expression, ExpressionError},
+    wav::reader::Wave,
+};
+
+use super::{GenInfo, Oscillator, PerChannel};
+
+/// Every frame is resampled to this many samples.
+pub const TABLE_SIZE: usize = 2048;
+/// Frame size of wavetable files unless told otherwise.
+pub const DEFAULT_FRAME_SIZE: usize = 2048;
+
+/// One level per octave, from all `TABLE_SIZE / 2` harmonics down to just the fundamental.
+const MIP_LEVELS: usize = TABLE_SIZE.trailing_zeros() as usize;
+
+/// A single cycle waveform, with band limited copies for higher notes.
+#[derive(Debug)]
+pub struct Frame {
+    /// Level `l` has harmonics up to `TABLE_SIZE / 2 >> l`.
+    mips: Vec<Vec<f64>>,
+}
+
+impl Frame {
+    pub fn new(cycle: &[f64]) -> Self {
+        let len = cycle.len() as f64;
+        let mut spectrum: Vec<_> = (0..TABLE_SIZE)
+            .map(|i| {
+                let x = i as f64 * len / TABLE_SIZE as f64;
+                Complex::new(interpolate_cycle(cycle, x), 0.)
+            })
+            .collect();
+        fft::fft(&mut spectrum);
+
+        // no DC in an oscillator
+        spectrum[0] = Complex::default();
+
+        let mips = (0..MIP_LEVELS)
+            .map(|level| {
+                let harmonics = (TABLE_SIZE / 2) >> level;
+
+                let mut bins = spectrum.clone();
+                for (k, bin) in bins.iter_mut().enumerate() {
+                    let harmonic = k.min(TABLE_SIZE - k);
+                    if harmonic > harmonics {
+                        *bin = Complex::default();
+                    }
+                }
+
+                fft::ifft(&mut bins);
+                bins.iter().map(|c| c.re).collect()
+            })
+            .collect();
+
+        Self { mips }
+    }
+
+    /// Reads the frame at `p` (0..1) with harmonics up to `max_harmonic`.
+    fn get(&self, p: f64, max_harmonic: f64) -> f64 {
+        // even the fundamental is above nyquist
+        if max_harmonic < 1. {
+            return 0.;
+        }
+
+        let mut level = 0;
+        while level + 1 < MIP_LEVELS && ((TABLE_SIZE / 2) >> level) as f64 > max_harmonic {
+            level += 1;
+        }
+
+        let table = &self.mips[level];
+        let x = p * TABLE_SIZE as f64;
+        let i = x.floor() as usize;
+        let frac = x - i as f64;
+
+        let a = table[i % TABLE_SIZE];
+        let b = table[(i + 1) % TABLE_SIZE];
+        a + (b - a) * frac
+    }
+}
+
+/// Linear interpolation in a periodic buffer.
+fn interpolate_cycle(cycle: &[f64], x: f64) -> f64 {
+    let i = x.floor() as usize;
+    let frac = x - i as f64;
+
+    let a = cycle[i % cycle.len()];
+    let b = cycle[(i + 1) % cycle.len()];
+    a + (b - a) * frac
+}
+
+/// Cuts the first channel of `wave` into `frame_size` long frames.
+pub fn frames_from_wave(wave: &Wave, frame_size: usize) -> Vec<Frame> {
+    let frames = wave.frames();
+    if frames == 0 {
+        return vec![];
+    }
+
+    // files shorter than a frame are a single cycle
+    if frames <= frame_size {
+        let cycle: Vec<_> = (0..frames).map(|i| wave.get(i, 0)).collect();
+        return vec![Frame::new(&cycle)];
+    }
+
+    (0..frames / frame_size)
+        .map(|f| {
+            let cycle: Vec<_> = (0..frame_size)
+                .map(|i| wave.get(f * frame_size + i, 0))
+                .collect();
+            Frame::new(&cycle)
+        })
+        .collect()
+}
+
+/// The built-in tables, `basic` and `pwm`.
+pub fn builtin_frames(name: &str) -> Option<Vec<Frame>> {
+    let cycle = |f: &dyn Fn(f64) -> f64| -> Frame {
+        let cycle: Vec<_> = (0..TABLE_SIZE)
+            .map(|i| f(i as f64 / TABLE_SIZE as f64))
+            .collect();
+        Frame::new(&cycle)
+    };
+
+    Some(match name {
+        // sine -> triangle -> saw -> square
+        "basic" => vec![
+            cycle(&|p| super::sine(p, 1., 0.)),
+            cycle(&|p| super::triangle(p, 1., 0.)),
+            cycle(&|p| super::saw(p, 1., 0.)),
+            cycle(&|p| super::square(p, 1., 0.)),
+        ],
+
+        // pulse width from 50% down to 5%
+        "pwm" => (0..16)
+            .map(|i| {
+                let width = 0.5 - 0.45 * i as f64 / 15.;
+                cycle(&|p| super::pulse(p, 1., 0., width))
+            })
+            .collect(),
+
+        _ => return None,
+    })
+}
+
+#[derive(Debug)]
+pub struct WavetableSource {
+    /// File path or the name of a built-in table.
+    pub(crate) name: String,
+    pub(crate) frames: Vec<Frame>,
+
+    pub(crate) freq: Expression,
+    /// Morphs through the frames, 0..1.
+    pub(crate) position: Expression,
+
+    pub(crate) osc: PerChannel<Oscillator>,
+}
+
+impl WavetableSource {
+    pub fn new(name: String, frames: Vec<Frame>, freq: Expression, position: Expression) -> Self {
+        Self {
+            name,
+            frames,
+            freq,
+            position,
+            osc: PerChannel::default(),
+        }
+    }
+
+    pub fn gen(&mut self, gi: GenInfo) -> Result<f64, ExpressionError> {
+        let freq = self.freq.evaluate(Some(gi))?;
+        let position = self.position.evaluate(Some(gi))?.clamp(0., 1.);
+
+        let osc = self.osc.get(gi.channel);
+        let p = osc.advance(gi.secs(), freq).rem_euclid(1.);
+        let max_harmonic = gi.samplerate as f64 / 2. / freq.abs().max(f64::EPSILON);
+
+        let x = position * (self.frames.len() - 1) as f64;
+        let i = x.floor() as usize;
+        let frac = x - i as f64;
+
+        let a = self.frames[i].get(p, max_harmonic);
+        Ok(match self.frames.get(i + 1) {
+            Some(next) if frac > 0. => a + (next.get(p, max_harmonic) - a) * frac,
+            _ => a,
+        })
+    }
+}
+
+impl Display for WavetableSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} Hz wavetable \"{}\" ({} frames, position: {})",
+            self.freq,
+            self.name,
+            self.frames.len(),
+            self.position
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gen::tests::{alias_ratio, render};
+
+    #[test]
+    fn wavetables_dont_alias() {
+        const F0: usize = 3010;
+
+        for (table, position) in [("basic", "1"), ("pwm", "1")] {
+            let x = render(&format!(
+                "\"t\" 0.1s on 1 wavetable({table}, {F0} hz, {position}, :) on * @ 1 {{}}"
+            ));
+
+            let ratio = 10. * alias_ratio(&x, F0).log10();
+            assert!(ratio < -40., "{table}: aliases at {ratio:.1} dB");
+        }
+
+        // nothing of a note above nyquist can be played
+        let x = render("\"t\" 0.1s on 1 wavetable(basic, 30000 hz, 1, :) on * @ 1 {}");
+        let peak = x.iter().fold(0f64, |p, v| p.max(v.abs()));
+        assert!(peak < 1e-9, "{peak} above nyquist");
+    }
+}
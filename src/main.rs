@@ -1,33 +1,118 @@
 #![feature(try_trait_v2)]
 
+use anyhow::{anyhow, bail};
+
+pub mod fft;
 pub mod gen;
+pub mod master;
 pub mod parse;
 pub mod pcm;
 pub mod wav;
 
-fn main() -> anyhow::Result<()> {
+struct Args {
+    source_file: String,
+    output_file: String,
+
+    sample_rate: usize,
+    format: wav::SampleFormat,
+    master: master::MasterOptions,
+}
+
+fn parse_args() -> anyhow::Result<Args> {
     let mut args = std::env::args();
     let _ = args.next();
-    let source_file = args.next().unwrap_or_else(|| "test_format.txt".to_string());
-    let output_file = args.next().unwrap_or_else(|| "test.wav".to_string());
 
-    let sample_rate = 44100;
-    let bytes_per_sample = 2;
+    let mut positional = vec![];
+    let mut sample_rate = 44100;
+    let mut bits = None;
+    let mut float = false;
+    let mut master = master::MasterOptions::default();
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| anyhow!("{name} needs a value"));
+
+        match &arg[..] {
+            "-r" | "--rate" => sample_rate = value(&arg)?.parse()?,
+            "-b" | "--bits" => bits = Some(value(&arg)?.parse()?),
+            "--float" => float = true,
+
+            "--peak" => master.normalize = master::Normalize::Peak(value(&arg)?.parse()?),
+            "--lufs" => master.normalize = master::Normalize::Loudness(value(&arg)?.parse()?),
+            "--clip" => {
+                let name = value(&arg)?;
+                master.clip = Some(
+                    master::Clip::from_name(&name)
+                        .ok_or_else(|| anyhow!("Unknown clipping {name}, try hard or soft"))?,
+                );
+            }
+            "--dither" => {
+                let name = value(&arg)?;
+                master.dither = master::Dither::from_name(&name)
+                    .ok_or_else(|| anyhow!("Unknown dither {name}, try tpdf, shaped or none"))?;
+            }
+
+            a if a.starts_with('-') && a.len() > 1 => bail!("Unknown option {a}"),
+
+            _ => positional.push(arg),
+        }
+    }
 
-    let source = std::fs::read_to_string(&source_file)?;
-    let mut song = parse::get_song(&source_file, &source)?;
+    if sample_rate == 0 {
+        bail!("Sample rate has to be positive");
+    }
 
-    gen::print_song(&song);
+    // --float without -b means 32 bit float
+    let bits = bits.unwrap_or(if float { 32 } else { 16 });
+    let format = wav::SampleFormat::new(bits, float)
+        .ok_or_else(|| anyhow!("Unsupported sample format: {bits} bit (float: {float})"))?;
 
-    let data = pcm::generate_pcm(&mut song, sample_rate)?;
+    let mut positional = positional.into_iter();
+
+    Ok(Args {
+        source_file: positional
+            .next()
+            .unwrap_or_else(|| "test_format.txt".to_string()),
+        output_file: positional.next().unwrap_or_else(|| "test.wav".to_string()),
 
-    wav::write_to_wav(
-        song.channels,
         sample_rate,
-        bytes_per_sample,
-        &data,
-        std::fs::File::create(output_file)?,
-    )?;
+        format,
+        master,
+    })
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = parse_args()?;
+
+    let source = std::fs::read_to_string(&args.source_file)?;
+    let mut song = parse::get_song(&args.source_file, &source)?;
+
+    let mut master = master::Master::new(args.master, args.format);
+    if master.needs_levels() {
+        // songs can't be rewound, so the second pass renders a fresh copy
+        let levels = pcm::measure(&mut song, args.sample_rate)?;
+        master.normalize(&levels);
+
+        song = parse::get_song(&args.source_file, &source)?;
+    }
+
+    if args.output_file == "-" {
+        let stdout = std::io::stdout().lock();
+        pcm::stream_wav(
+            &mut song,
+            args.sample_rate,
+            args.format,
+            &mut master,
+            stdout,
+        )?;
+    } else {
+        gen::print_song(&song);
+
+        let file = std::io::BufWriter::new(std::fs::File::create(args.output_file)?);
+        pcm::write_wav(&mut song, args.sample_rate, args.format, &mut master, file)?;
+    }
+
+    // stdout might be the wave
+    eprintln!("{}", master.report());
 
     Ok(())
 }
@@ -0,0 +1,479 @@
+use std::{collections::VecDeque, f64::consts::PI, fmt::Display};
+
+use crate::{
+    gen::{
+        dynamics::{db_to_gain, gain_to_db},
+        filter::{Biquad, Coefficients},
+        noise::Rng,
+    },
+    wav::SampleFormat,
+};
+
+/// Where the soft clipper starts bending the signal.
+const SOFT_KNEE: f64 = 0.8;
+/// Noise shaping error feedback can't run away when the quantizer saturates.
+const MAX_ERROR: f64 = 4.;
+
+/// Loudness is measured in 400 ms blocks, every 100 ms.
+const HOPS_PER_BLOCK: usize = 4;
+const ABSOLUTE_GATE: f64 = -70.;
+const RELATIVE_GATE: f64 = -10.;
+
+/// Analog prototypes of the K-weighting filters, fitted to the 48 kHz coefficients of
+/// BS.1770 (as in libebur128) so they can be made for any sample rate.
+const SHELF_FREQ: f64 = 1681.974450955533;
+const SHELF_GAIN: f64 = 3.999843853973347;
+const SHELF_Q: f64 = 0.7071752369554196;
+/// The shelf's gain half way up is its full gain to this power.
+const SHELF_MID: f64 = 0.4996667741545416;
+const HIGHPASS_FREQ: f64 = 38.13547087602444;
+const HIGHPASS_Q: f64 = 0.5003270373238773;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Normalize {
+    #[default]
+    Off,
+    /// Target sample peak in dBFS.
+    Peak(f64),
+    /// Target integrated loudness in LUFS.
+    Loudness(f64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clip {
+    Hard,
+    /// Bends smoothly into full scale above [`SOFT_KNEE`].
+    Soft,
+}
+
+impl Clip {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "hard" => Self::Hard,
+            "soft" => Self::Soft,
+
+            _ => return None,
+        })
+    }
+
+    fn apply(self, v: f64) -> f64 {
+        match self {
+            Self::Hard => v.clamp(-1., 1.),
+            Self::Soft if v.abs() <= SOFT_KNEE => v,
+            Self::Soft => {
+                let range = 1. - SOFT_KNEE;
+                v.signum() * (SOFT_KNEE + range * f64::tanh((v.abs() - SOFT_KNEE) / range))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dither {
+    Off,
+    /// Triangular noise of ±1 step.
+    #[default]
+    Tpdf,
+    /// Triangular noise with the error pushed up towards nyquist, `(1 - z^-1)^2`.
+    Shaped,
+}
+
+impl Dither {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "none" | "off" => Self::Off,
+            "tpdf" => Self::Tpdf,
+            "shaped" => Self::Shaped,
+
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MasterOptions {
+    pub normalize: Normalize,
+    /// `None` clips integer output hard and leaves float output alone.
+    pub clip: Option<Clip>,
+    pub dither: Dither,
+}
+
+fn k_weighting(samplerate: f64) -> [Coefficients; 2] {
+    let k = f64::tan(PI * SHELF_FREQ / samplerate);
+    let a0 = 1. + k / SHELF_Q + k * k;
+    let vh = db_to_gain(SHELF_GAIN);
+    let vb = vh.powf(SHELF_MID);
+    let shelf = Coefficients::from_normalized(
+        [
+            (vh + vb * k / SHELF_Q + k * k) / a0,
+            2. * (k * k - vh) / a0,
+            (vh - vb * k / SHELF_Q + k * k) / a0,
+        ],
+        [2. * (k * k - 1.) / a0, (1. - k / SHELF_Q + k * k) / a0],
+    );
+
+    let k = f64::tan(PI * HIGHPASS_FREQ / samplerate);
+    let a0 = 1. + k / HIGHPASS_Q + k * k;
+    let highpass = Coefficients::from_normalized(
+        [1., -2., 1.],
+        [2. * (k * k - 1.) / a0, (1. - k / HIGHPASS_Q + k * k) / a0],
+    );
+
+    [shelf, highpass]
+}
+
+/// ITU-R BS.1770 integrated loudness, every channel weighted the same.
+#[derive(Debug)]
+pub struct LoudnessMeter {
+    coefficients: [Coefficients; 2],
+    filters: Vec<[Biquad; 2]>,
+
+    hop: usize,
+    pos: usize,
+    current: f64,
+    hops: VecDeque<f64>,
+    blocks: Vec<f64>,
+
+    /// For songs shorter than a block.
+    total: f64,
+    frames: usize,
+}
+
+impl LoudnessMeter {
+    pub fn new(channels: usize, samplerate: usize) -> Self {
+        Self {
+            coefficients: k_weighting(samplerate as f64),
+            filters: vec![[Biquad::default(); 2]; channels],
+            hop: (samplerate / 10).max(1),
+            pos: 0,
+            current: 0.,
+            hops: VecDeque::new(),
+            blocks: vec![],
+            total: 0.,
+            frames: 0,
+        }
+    }
+
+    pub fn add(&mut self, frame: &[f64]) {
+        for (v, [shelf, highpass]) in frame.iter().zip(&mut self.filters) {
+            let v = shelf.process(&self.coefficients[0], *v);
+            let v = highpass.process(&self.coefficients[1], v);
+            self.current += v * v;
+        }
+
+        self.pos += 1;
+        if self.pos == self.hop {
+            self.total += self.current;
+            self.frames += self.hop;
+
+            self.hops.push_back(std::mem::take(&mut self.current));
+            self.pos = 0;
+
+            if self.hops.len() == HOPS_PER_BLOCK {
+                let sum: f64 = self.hops.iter().sum();
+                self.blocks.push(sum / (HOPS_PER_BLOCK * self.hop) as f64);
+                self.hops.pop_front();
+            }
+        }
+    }
+
+    /// In LUFS, `-inf` for silence.
+    pub fn loudness(&self) -> f64 {
+        let lufs = |power: f64| -0.691 + 10. * power.log10();
+        let mean = |blocks: &[f64]| blocks.iter().sum::<f64>() / blocks.len() as f64;
+
+        if self.blocks.is_empty() {
+            let frames = self.frames + self.pos;
+            return match frames {
+                0 => f64::NEG_INFINITY,
+                n => lufs((self.total + self.current) / n as f64),
+            };
+        }
+
+        let audible: Vec<f64> = self
+            .blocks
+            .iter()
+            .copied()
+            .filter(|b| lufs(*b) > ABSOLUTE_GATE)
+            .collect();
+        if audible.is_empty() {
+            return f64::NEG_INFINITY;
+        }
+
+        let gate = lufs(mean(&audible)) + RELATIVE_GATE;
+        let gated: Vec<f64> = audible.into_iter().filter(|b| lufs(*b) > gate).collect();
+
+        lufs(mean(&gated))
+    }
+}
+
+#[derive(Debug)]
+pub struct Levels {
+    pub peak: f64,
+    pub meter: LoudnessMeter,
+}
+
+impl Levels {
+    pub fn new(channels: usize, samplerate: usize) -> Self {
+        Self {
+            peak: 0.,
+            meter: LoudnessMeter::new(channels, samplerate),
+        }
+    }
+
+    pub fn add(&mut self, frame: &[f64]) {
+        for v in frame {
+            self.peak = self.peak.max(v.abs());
+        }
+        self.meter.add(frame);
+    }
+}
+
+#[derive(Debug)]
+pub struct Master {
+    options: MasterOptions,
+    clip: Option<Clip>,
+    /// Size of a quantization step, if the output is integer.
+    step: Option<f64>,
+    gain: f64,
+    loudness: Option<f64>,
+
+    rng: Rng,
+    /// The last two quantization errors of every channel, in steps.
+    errors: Vec<[f64; 2]>,
+
+    peak: f64,
+    clipped: usize,
+    samples: usize,
+}
+
+impl Master {
+    pub fn new(options: MasterOptions, format: SampleFormat) -> Self {
+        let step = format.full_scale().map(f64::recip);
+
+        Self {
+            options,
+            // integers can't go past full scale anyway
+            clip: options.clip.or(step.map(|_| Clip::Hard)),
+            step,
+            gain: 1.,
+            loudness: None,
+            rng: Rng::new(0),
+            errors: vec![],
+            peak: 0.,
+            clipped: 0,
+            samples: 0,
+        }
+    }
+
+    pub fn needs_levels(&self) -> bool {
+        self.options.normalize != Normalize::Off
+    }
+
+    pub fn normalize(&mut self, levels: &Levels) {
+        self.gain = match self.options.normalize {
+            Normalize::Off => 1.,
+            Normalize::Peak(target) if levels.peak > 0. => db_to_gain(target) / levels.peak,
+            Normalize::Loudness(target) => {
+                let loudness = levels.meter.loudness();
+                self.loudness = Some(loudness);
+
+                match loudness.is_finite() {
+                    true => db_to_gain(target - loudness),
+                    false => 1.,
+                }
+            }
+
+            // silence
+            Normalize::Peak(_) => 1.,
+        };
+    }
+
+    /// Processes interleaved samples in place.
+    pub fn process(&mut self, samples: &mut [f64], channels: usize) {
+        if self.errors.len() < channels {
+            self.errors.resize(channels, [0.; 2]);
+        }
+
+        for frame in samples.chunks_exact_mut(channels) {
+            for (v, errors) in frame.iter_mut().zip(&mut self.errors) {
+                let x = *v * self.gain;
+                self.peak = self.peak.max(x.abs());
+
+                let x = match self.clip {
+                    Some(clip) => {
+                        if x.abs() > 1. {
+                            self.clipped += 1;
+                        }
+                        clip.apply(x)
+                    }
+                    None => x,
+                };
+
+                *v = match self.step {
+                    Some(step) => {
+                        Self::quantize(self.options.dither, &mut self.rng, errors, x, step)
+                    }
+                    None => x,
+                };
+            }
+        }
+
+        self.samples += samples.len();
+    }
+
+    fn quantize(dither: Dither, rng: &mut Rng, errors: &mut [f64; 2], x: f64, step: f64) -> f64 {
+        let x = x / step;
+        let tpdf = |rng: &mut Rng| (rng.next_bipolar() + rng.next_bipolar()) / 2.;
+
+        let steps = match dither {
+            Dither::Off => x.round(),
+            Dither::Tpdf => (x + tpdf(rng)).round(),
+            Dither::Shaped => {
+                let wanted = x - 2. * errors[0] + errors[1];
+                let steps = (wanted + tpdf(rng)).round();
+
+                errors[1] = errors[0];
+                errors[0] = (steps - wanted).clamp(-MAX_ERROR, MAX_ERROR);
+                steps
+            }
+        };
+
+        let max = step.recip();
+        steps.clamp(-max, max) * step
+    }
+
+    pub fn report(&self) -> Report {
+        Report {
+            gain: self.gain,
+            loudness: self.loudness,
+            peak: self.peak,
+            clipped: self.clip.map(|_| self.clipped),
+            samples: self.samples,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Report {
+    pub gain: f64,
+    pub loudness: Option<f64>,
+    /// Before clipping.
+    pub peak: f64,
+    /// `None` if clipping was off.
+    pub clipped: Option<usize>,
+    pub samples: usize,
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.gain != 1. {
+            write!(f, "Normalized by {:+.1} dB", gain_to_db(self.gain))?;
+            if let Some(loudness) = self.loudness {
+                write!(f, " (measured {loudness:.1} LUFS)")?;
+            }
+            write!(f, ", ")?;
+        }
+
+        write!(f, "peak {:.1} dBFS", gain_to_db(self.peak))?;
+
+        if let Some(clipped) = self.clipped {
+            let percent = match self.samples {
+                0 => 0.,
+                n => clipped as f64 / n as f64 * 100.,
+            };
+            write!(
+                f,
+                ", {clipped} of {} samples clipped ({percent:.3}%)",
+                self.samples
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::TAU;
+
+    use super::*;
+
+    /// Loudness of a 5 s full scale sine on the first of two channels.
+    fn sine_loudness(freq: f64, samplerate: usize) -> f64 {
+        let mut meter = LoudnessMeter::new(2, samplerate);
+        for n in 0..samplerate * 5 {
+            let v = f64::sin(TAU * freq * n as f64 / samplerate as f64);
+            meter.add(&[v, 0.]);
+        }
+
+        meter.loudness()
+    }
+
+    #[test]
+    fn full_scale_sine_is_minus_three_lufs() {
+        // the reference tone from BS.1770, 997 Hz at 0 dBFS on one channel
+        let loudness = sine_loudness(997., 48000);
+        assert!((loudness + 3.01).abs() < 0.05, "{loudness} LUFS");
+    }
+
+    #[test]
+    fn k_weighting_matches_the_standard_at_48k() {
+        // the coefficients as printed in BS.1770
+        let standard = [
+            Coefficients::from_normalized(
+                [1.53512485958697, -2.69169618940638, 1.19839281085285],
+                [-1.69065929318241, 0.73248077421585],
+            ),
+            Coefficients::from_normalized([1., -2., 1.], [-1.99004745483398, 0.99007225036621]),
+        ];
+
+        for (ours, theirs) in k_weighting(48000.).iter().zip(&standard) {
+            let (mut a, mut b) = (Biquad::default(), Biquad::default());
+            for n in 0..1000 {
+                let x = if n == 0 { 1. } else { 0. };
+                let (a, b) = (a.process(ours, x), b.process(theirs, x));
+                assert!((a - b).abs() < 1e-9, "{n}: {a} vs {b}");
+            }
+        }
+    }
+
+    #[test]
+    fn loudness_follows_the_k_weighting_at_every_rate() {
+        // -3.70 LUFS unweighted plus the response of the standard's filters
+        for (freq, expected) in [(100., -4.84), (997., -3.01), (10000., 0.34)] {
+            for rate in [44100, 48000, 96000] {
+                let loudness = sine_loudness(freq, rate);
+                assert!(
+                    (loudness - expected).abs() < 0.05,
+                    "{freq} Hz at {rate}: {loudness} LUFS"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn float_output_is_only_clipped_when_asked() {
+        let clip = |clip, format| {
+            let mut master = Master::new(
+                MasterOptions {
+                    clip,
+                    dither: Dither::Off,
+                    ..Default::default()
+                },
+                format,
+            );
+            let mut samples = [1.5, 0.];
+            master.process(&mut samples, 2);
+
+            (samples, master.report().clipped)
+        };
+
+        assert_eq!(clip(None, SampleFormat::Float(32)), ([1.5, 0.], None));
+        assert_eq!(
+            clip(Some(Clip::Hard), SampleFormat::Float(32)),
+            ([1., 0.], Some(1))
+        );
+        assert_eq!(clip(None, SampleFormat::Int(8)), ([1., 0.], Some(1)));
+    }
+}
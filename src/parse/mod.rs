@@ -9,7 +9,16 @@ use self::{
     source::{Source, StringSource},
     tokenizer::{Number, Token, TokenType as Ty, Tokenizer},
 };
-use crate::gen::{self, Channels, PeriodicSource, Song, SourceType};
+use crate::{
+    gen::{
+        self, distortion::MAX_OVERSAMPLING, AdditiveSource, Balance, Bitcrush, Channels,
+        Convolution, Curve, Delay, Drive, DtmfSource, Dynamics, DynamicsKind, Filter, FilterKind,
+        FmSource, Gains, GrainsSource, ImpulseSource, Modulation, ModulationKind, NoiseColor,
+        NoiseSource, Pan, PanLaw, PerChannel, PeriodicSource, PluckSource, Reverb, SampleSource,
+        Song, SourceType, SweepCurve, SweepSource, Waveshaper, WavetableSource, Width,
+    },
+    wav::reader::{self as wav_reader, WavError},
+};
 use thiserror::Error as ThisError;
 
 pub mod printing;
@@ -37,6 +46,28 @@ pub enum ParserError<S> {
     #[error("Expected {expected:?}, found {found:?}")]
     UnexpectedExact { expected: Ty, found: Ty },
 
+    #[error("Unknown option {0}")]
+    UnknownOption(String),
+
+    #[error("Missing or invalid {0}")]
+    InvalidOption(String),
+
+    #[error("Invalid DTMF key {0:?}")]
+    InvalidDtmfKey(char),
+
+    #[error("Unknown wavetable {0}")]
+    UnknownTable(String),
+
+    #[error("Panning, width and ping-pong delays need the source on all channels (on *)")]
+    NotAllChannels,
+
+    #[error("Couldn't load '{path}'")]
+    Load {
+        path: String,
+        #[source]
+        err: WavError,
+    },
+
     #[error(transparent)]
     Expression(#[from] ExpressionError),
 }
@@ -110,6 +141,9 @@ impl<'d, 's, S: Source> Parser<'d, 's, S> {
             _ => return Res::Err(ParsErr::MissingChannels),
         };
 
+        // master effects
+        let effects = self.parse_effects(self.song_length_s)?;
+
         while let Some(s) = self.parse_source().to_res_opt()? {
             sources.push(s);
         }
@@ -118,6 +152,7 @@ impl<'d, 's, S: Source> Parser<'d, 's, S> {
             channels: self.song_channels,
             length_s: self.song_length_s,
             sources,
+            effects,
             name,
         })
     }
@@ -175,59 +210,341 @@ impl<'d, 's, S: Source> Parser<'d, 's, S> {
         self.eat(Ty::LeftParenthesis)?;
 
         let ty = match wave_type {
-            "sin" | "sine" | "saw" | "tri" | "triangle" | "square" => {
-                let freq = self.parse_expression(|t| {
-                    if let Some("Hz" | "hz") = t.text() {
-                        Terminate::Yes {
-                            discard_token: true,
+            "sin" | "sine" | "saw" | "tri" | "triangle" | "square" | "pulse" => {
+                let freq = self.parse_freq()?;
+                self.eat(Ty::Comma)?;
+
+                let phase = self.parse_phase()?;
+                self.eat(Ty::Comma)?;
+
+                let ty = match wave_type {
+                    "sin" => PeriodicSource::Sine,
+                    "sine" => PeriodicSource::Sine,
+                    "saw" => PeriodicSource::Saw,
+                    "tri" | "triangle" => PeriodicSource::Triangle,
+                    "square" => PeriodicSource::Square,
+                    "pulse" => {
+                        let width = self.parse_arg()?;
+                        self.eat(Ty::Comma)?;
+
+                        PeriodicSource::Pulse { width }
+                    }
+
+                    _ => unreachable!(),
+                };
+
+                let mut band_limited = true;
+                self.parse_options(|_, name| {
+                    match name {
+                        "naive" => band_limited = false,
+                        "bl" => band_limited = true,
+
+                        _ => return Res::Err(ParsErr::UnknownOption(name.to_string())),
+                    }
+
+                    Res::Some(())
+                })?;
+
+                SourceType::Periodic {
+                    freq,
+                    phase,
+                    ty,
+                    band_limited,
+                    osc: PerChannel::default(),
+                }
+            }
+
+            "sample" => {
+                let path = match self.get_token()? {
+                    Token {
+                        ty: Ty::StringLiteral(path),
+                        ..
+                    } => path,
+
+                    t => return Res::Err(ParsErr::Unexpected(t.ty)),
+                };
+                self.eat(Ty::Comma)?;
+
+                let wave = self.load_wave(&wave_type_t, &path)?;
+                let mut sample = SampleSource::new(path, wave);
+
+                self.parse_options(|p, name| {
+                    match name {
+                        "rate" => sample.rate = Some(p.parse_arg()?),
+                        "pitch" => sample.pitch = Some(p.parse_arg()?),
+                        "loop" => sample.looping = true,
+                        "from" => sample.from = p.parse_duration()?,
+                        "to" => sample.to = Some(p.parse_duration()?),
+
+                        _ => return Res::Err(ParsErr::UnknownOption(name.to_string())),
+                    }
+
+                    Res::Some(())
+                })?;
+
+                SourceType::Sample(sample)
+            }
+
+            "grains" => {
+                let path = match self.get_token()? {
+                    Token {
+                        ty: Ty::StringLiteral(path),
+                        ..
+                    } => path,
+
+                    t => return Res::Err(ParsErr::Unexpected(t.ty)),
+                };
+                self.eat(Ty::Comma)?;
+
+                let density = self.parse_arg()?;
+                self.eat(Ty::Comma)?;
+
+                let size = self.parse_duration()?;
+                self.eat(Ty::Comma)?;
+
+                let position = self.parse_arg()?;
+                self.eat(Ty::Comma)?;
+
+                let pitch = self.parse_arg()?;
+                self.eat(Ty::Comma)?;
+
+                let wave = self.load_wave(&wave_type_t, &path)?;
+                let mut grains = GrainsSource::new(path, wave, density, size, position, pitch);
+
+                self.parse_options(|p, name| {
+                    match name {
+                        "spread" => grains.spread = p.parse_arg()?,
+                        "detune" => grains.detune = p.parse_arg()?,
+                        "seed" => grains.seed = p.parse_integer()? as u64,
+
+                        _ => return Res::Err(ParsErr::UnknownOption(name.to_string())),
+                    }
+
+                    Res::Some(())
+                })?;
+
+                SourceType::Grains(grains)
+            }
+
+            "sweep" => {
+                let from = self.parse_freq()?;
+                self.eat(Ty::Comma)?;
+
+                let to = self.parse_freq()?;
+                self.eat(Ty::Comma)?;
+
+                let curve = self.parse_named(SweepCurve::from_name)?;
+                self.eat(Ty::Comma)?;
+
+                SourceType::Sweep(SweepSource { from, to, curve })
+            }
+
+            "impulse" => {
+                let period = self.parse_duration()?;
+                self.eat(Ty::Comma)?;
+
+                SourceType::Impulse(ImpulseSource::new(period))
+            }
+
+            "dtmf" => {
+                let keys = match self.get_token()? {
+                    Token {
+                        ty: Ty::StringLiteral(keys),
+                        ..
+                    } => keys,
+
+                    t => return Res::Err(ParsErr::Unexpected(t.ty)),
+                };
+                if let Some(c) = keys.chars().find(|c| gen::signal::dtmf_tones(*c).is_none()) {
+                    return Res::Err(ParsErr::InvalidDtmfKey(c));
+                }
+                self.eat(Ty::Comma)?;
+
+                let mut dtmf = DtmfSource::new(keys);
+                self.parse_options(|p, name| {
+                    match name {
+                        "tone" => dtmf.tone = p.parse_duration()?,
+                        "gap" => dtmf.gap = p.parse_duration()?,
+
+                        _ => return Res::Err(ParsErr::UnknownOption(name.to_string())),
+                    }
+
+                    Res::Some(())
+                })?;
+
+                SourceType::Dtmf(dtmf)
+            }
+
+            "silence" => SourceType::Silence,
+
+            "noise" => {
+                let color = self.parse_named(NoiseColor::from_name)?;
+                self.eat(Ty::Comma)?;
+
+                let mut seed = 0;
+                self.parse_options(|p, name| {
+                    match name {
+                        "seed" => seed = p.parse_integer()? as u64,
+
+                        _ => return Res::Err(ParsErr::UnknownOption(name.to_string())),
+                    }
+
+                    Res::Some(())
+                })?;
+
+                SourceType::Noise(NoiseSource::new(color, seed))
+            }
+
+            "wavetable" => {
+                let t = self.get_token()?;
+                let (name, frames) = match &t.ty {
+                    Ty::StringLiteral(path) => (path.clone(), None),
+                    Ty::Identifier => {
+                        let name = t.text().expect("Couldn't get identifier contents");
+                        match gen::wavetable::builtin_frames(name) {
+                            Some(frames) => (name.to_string(), Some(frames)),
+                            None => return Res::Err(ParsErr::UnknownTable(name.to_string())),
                         }
-                    } else {
-                        Terminate::No
                     }
+
+                    _ => return Res::Err(ParsErr::Unexpected(t.ty)),
+                };
+                self.eat(Ty::Comma)?;
+
+                let freq = self.parse_freq()?;
+                self.eat(Ty::Comma)?;
+
+                let position = self.parse_arg()?;
+                self.eat(Ty::Comma)?;
+
+                let mut frame_size = gen::wavetable::DEFAULT_FRAME_SIZE;
+                self.parse_options(|p, name| {
+                    match name {
+                        "size" => frame_size = p.parse_integer()?.max(1) as usize,
+
+                        _ => return Res::Err(ParsErr::UnknownOption(name.to_string())),
+                    }
+
+                    Res::Some(())
+                })?;
+
+                let frames = match frames {
+                    Some(frames) => frames,
+                    None => {
+                        let wave = self.load_wave(&wave_type_t, &name)?;
+                        gen::wavetable::frames_from_wave(&wave, frame_size)
+                    }
+                };
+                if frames.is_empty() {
+                    return Res::Err(ParsErr::InvalidOption("wavetable".to_string()));
+                }
+
+                SourceType::Wavetable(WavetableSource::new(name, frames, freq, position))
+            }
+
+            "pluck" => {
+                let freq = self.parse_freq()?;
+                self.eat(Ty::Comma)?;
+
+                let decay = self.parse_duration()?;
+                self.eat(Ty::Comma)?;
+
+                let brightness = self.parse_arg()?;
+                self.eat(Ty::Comma)?;
+
+                let mut seed = 0;
+                self.parse_options(|p, name| {
+                    match name {
+                        "seed" => seed = p.parse_integer()? as u64,
+
+                        _ => return Res::Err(ParsErr::UnknownOption(name.to_string())),
+                    }
+
+                    Res::Some(())
                 })?;
 
+                SourceType::Pluck(PluckSource::new(freq, decay, brightness, seed))
+            }
+
+            "additive" => {
+                let freq = self.parse_freq()?;
+                self.eat(Ty::Comma)?;
+
+                let phase = self.parse_phase()?;
                 self.eat(Ty::Comma)?;
 
-                let mut rad = false;
-                let phase = self.parse_expression(|t| {
-                    if let Some("rad") = t.text() {
-                        rad = true;
-                        Terminate::Yes {
-                            discard_token: true,
+                let amplitudes = self.parse_list()?;
+                self.eat(Ty::Comma)?;
+
+                let mut additive = AdditiveSource::new(freq, phase, amplitudes);
+                self.parse_options(|p, name| {
+                    match name {
+                        "phases" => additive.phases = p.parse_list()?,
+                        "detune" => additive.detune = p.parse_list()?,
+
+                        _ => return Res::Err(ParsErr::UnknownOption(name.to_string())),
+                    }
+
+                    Res::Some(())
+                })?;
+
+                SourceType::Additive(additive)
+            }
+
+            "fm" => {
+                let mut carrier = None;
+                let mut fm = FmSource::new(Expression::zero());
+
+                self.parse_options(|p, name| {
+                    match name {
+                        "carrier" => carrier = Some(p.parse_freq()?),
+
+                        // the modulator of the default pair, an earlier list might not have one
+                        "ratio" => {
+                            let ratio = p.parse_arg()?;
+                            *fm.ratios
+                                .get_mut(1)
+                                .ok_or_else(|| ParsErr::InvalidOption(name.to_string()))? = ratio;
                         }
-                    } else if let Some("deg") = t.text() {
-                        rad = false;
-                        Terminate::Yes {
-                            discard_token: true,
+                        "index" => {
+                            let index = p.parse_arg()?;
+                            *fm.indices
+                                .get_mut(1)
+                                .ok_or_else(|| ParsErr::InvalidOption(name.to_string()))? = index;
                         }
-                    } else {
-                        Terminate::No
+                        "ratios" => fm.ratios = p.parse_list()?,
+                        "indices" => fm.indices = p.parse_list()?,
+
+                        // 1 based
+                        "algo" | "algorithm" => {
+                            fm.algorithm = usize::try_from(p.parse_integer()?)
+                                .ok()
+                                .and_then(|n| n.checked_sub(1))
+                                .filter(|n| *n < gen::fm::ALGORITHMS.len())
+                                .ok_or_else(|| ParsErr::InvalidOption("algo".to_string()))?;
+                        }
+                        "feedback" => fm.feedback = p.parse_arg()?,
+
+                        _ => return Res::Err(ParsErr::UnknownOption(name.to_string())),
                     }
+
+                    Res::Some(())
                 })?;
-                self.eat(Ty::Comma)?;
 
-                let phase = if rad {
-                    phase
-                } else {
-                    Expression::Mul(
-                        phase.into(),
-                        Expression::Lit(Number::Real(PI / 180.)).into(),
-                    )
+                fm.carrier = match carrier {
+                    Some(c) => c,
+                    None => return Res::Err(ParsErr::InvalidOption("carrier".to_string())),
                 };
 
-                SourceType::Periodic {
-                    freq,
-                    phase,
-                    ty: match wave_type {
-                        "sin" => PeriodicSource::Sine,
-                        "sine" => PeriodicSource::Sine,
-                        "saw" => PeriodicSource::Saw,
-                        "tri" | "triangle" => PeriodicSource::Triangle,
-                        "square" => PeriodicSource::Square,
-
-                        _ => unreachable!(),
-                    },
+                let operators = fm.ratios.len();
+                if !(2..=gen::fm::MAX_OPERATORS).contains(&operators) {
+                    return Res::Err(ParsErr::InvalidOption("ratios".to_string()));
                 }
+                if fm.indices.len() != operators {
+                    return Res::Err(ParsErr::InvalidOption("indices".to_string()));
+                }
+
+                SourceType::Fm(fm)
             }
 
             _ => return Res::Err(ParsErr::Unexpected(wave_type_t.ty)),
@@ -239,15 +556,10 @@ impl<'d, 's, S: Source> Parser<'d, 's, S> {
         let channels = self.parse_chan()?;
         let volume = self.parse_vol()?;
 
-        let mut effects = vec![];
-
-        let len_s = (end - start) * self.song_length_s;
-        if let Res::Some(_) = self.eat(Ty::LeftCurlyBraces) {
-            while let Res::Some(e) = self.parse_effect(len_s) {
-                effects.push(e);
-            }
-
-            self.eat(Ty::RightCurlyBraces)?;
+        let effects = self.parse_effects((end - start) * self.song_length_s)?;
+        if !matches!(channels, gen::Channels::All) && effects.iter().any(|e| e.ty.spans_channels())
+        {
+            return Res::Err(ParsErr::NotAllChannels);
         }
 
         Res::Some(gen::Source {
@@ -263,6 +575,29 @@ impl<'d, 's, S: Source> Parser<'d, 's, S> {
         })
     }
 
+    /// Parses an optional `{ ... }` block of effects.
+    fn parse_effects(&mut self, parent_len_s: f64) -> Res<Vec<gen::Effect>, ParsErr<S::Error>> {
+        let mut effects = vec![];
+
+        if let Res::Some(_) = self.eat(Ty::LeftCurlyBraces) {
+            // anything before the closing brace has to be an effect
+            loop {
+                let t = self.get_token()?;
+                let end = t.ty == Ty::RightCurlyBraces;
+                self.buffer.push(t);
+
+                if end {
+                    break;
+                }
+                effects.push(self.parse_effect(parent_len_s)?);
+            }
+
+            self.eat(Ty::RightCurlyBraces)?;
+        }
+
+        Res::Some(effects)
+    }
+
     fn parse_effect(&mut self, parent_len_s: f64) -> Res<gen::Effect, ParsErr<S::Error>> {
         let name_t = self.eat(Ty::Identifier)?;
         let name = name_t
@@ -270,18 +605,466 @@ impl<'d, 's, S: Source> Parser<'d, 's, S> {
             .get_text()
             .expect("Couldn't get identifier name");
 
-        let (start, end) = self.parse_timeframe(parent_len_s)?;
-
         let ty = match name {
             "fade_in" => gen::EffectType::FadeIn,
             "fade_out" => gen::EffectType::FadeOut,
 
-            _ => return Res::Err(ParsErr::Unexpected(name_t.ty)),
+            "delay" => {
+                self.eat(Ty::LeftParenthesis)?;
+
+                let mut time = None;
+                let mut feedback = None;
+                let mut mix = None;
+                let mut ping_pong = false;
+                self.parse_options(|p, name| {
+                    match name {
+                        "time" => time = Some(p.parse_duration()?),
+                        "feedback" => feedback = Some(p.parse_arg()?),
+                        "mix" => mix = Some(p.parse_arg()?),
+                        "pingpong" | "ping_pong" => ping_pong = true,
+
+                        _ => return Res::Err(ParsErr::UnknownOption(name.to_string())),
+                    }
+
+                    Res::Some(())
+                })?;
+                self.eat(Ty::RightParenthesis)?;
+
+                let mut delay = match time {
+                    Some(time) => Delay::new(time),
+                    None => return Res::Err(ParsErr::InvalidOption("time".to_string())),
+                };
+                if let Some(feedback) = feedback {
+                    delay.feedback = feedback;
+                }
+                if let Some(mix) = mix {
+                    delay.mix = mix;
+                }
+                delay.ping_pong = ping_pong;
+
+                gen::EffectType::Delay(delay)
+            }
+
+            "convolve" => {
+                self.eat(Ty::LeftParenthesis)?;
+
+                let path = match self.get_token()? {
+                    Token {
+                        ty: Ty::StringLiteral(path),
+                        ..
+                    } => path,
+
+                    t => return Res::Err(ParsErr::Unexpected(t.ty)),
+                };
+                let ir = self.load_wave(&name_t, &path)?;
+                let mut convolution = Convolution::new(path, ir);
+
+                if let Res::Some(_) = self.eat(Ty::Comma) {
+                    self.parse_options(|p, name| {
+                        match name {
+                            "mix" => convolution.mix = p.parse_arg()?,
+
+                            _ => return Res::Err(ParsErr::UnknownOption(name.to_string())),
+                        }
+
+                        Res::Some(())
+                    })?;
+                }
+                self.eat(Ty::RightParenthesis)?;
+
+                gen::EffectType::Convolution(convolution)
+            }
+
+            "reverb" => {
+                self.eat(Ty::LeftParenthesis)?;
+
+                let mut reverb = Reverb::default();
+                self.parse_options(|p, name| {
+                    match name {
+                        "size" => reverb.size = p.parse_arg()?,
+                        "damping" => reverb.damping = p.parse_arg()?,
+                        "predelay" | "pre_delay" => reverb.pre_delay = p.parse_duration()?,
+                        "width" => reverb.width = p.parse_arg()?,
+                        "mix" => reverb.mix = p.parse_arg()?,
+
+                        _ => return Res::Err(ParsErr::UnknownOption(name.to_string())),
+                    }
+
+                    Res::Some(())
+                })?;
+                self.eat(Ty::RightParenthesis)?;
+
+                gen::EffectType::Reverb(reverb)
+            }
+
+            "drive" => {
+                self.eat(Ty::LeftParenthesis)?;
+
+                let mut drive = Drive::new(self.parse_arg()?);
+                if let Res::Some(_) = self.eat(Ty::Comma) {
+                    self.parse_options(|p, name| {
+                        match name {
+                            "curve" => drive.curve = p.parse_named(Curve::from_name)?,
+                            "oversample" => drive.oversampling = p.parse_oversampling()?,
+                            "mix" => drive.mix = p.parse_arg()?,
+
+                            _ => return Res::Err(ParsErr::UnknownOption(name.to_string())),
+                        }
+
+                        Res::Some(())
+                    })?;
+                }
+                self.eat(Ty::RightParenthesis)?;
+
+                gen::EffectType::Drive(drive)
+            }
+
+            "waveshape" | "waveshaper" => {
+                self.eat(Ty::LeftParenthesis)?;
+
+                let mut shaper = Waveshaper::new(self.parse_arg()?);
+                if let Res::Some(_) = self.eat(Ty::Comma) {
+                    self.parse_options(|p, name| {
+                        match name {
+                            "oversample" => shaper.oversampling = p.parse_oversampling()?,
+                            "mix" => shaper.mix = p.parse_arg()?,
+
+                            _ => return Res::Err(ParsErr::UnknownOption(name.to_string())),
+                        }
+
+                        Res::Some(())
+                    })?;
+                }
+                self.eat(Ty::RightParenthesis)?;
+
+                gen::EffectType::Waveshaper(shaper)
+            }
+
+            "bitcrush" => {
+                self.eat(Ty::LeftParenthesis)?;
+
+                let bits = self.parse_arg()?;
+                self.eat(Ty::Comma)?;
+                let mut crush = Bitcrush::new(bits, self.parse_arg()?);
+                if let Res::Some(_) = self.eat(Ty::Comma) {
+                    self.parse_options(|p, name| {
+                        match name {
+                            "mix" => crush.mix = p.parse_arg()?,
+
+                            _ => return Res::Err(ParsErr::UnknownOption(name.to_string())),
+                        }
+
+                        Res::Some(())
+                    })?;
+                }
+                self.eat(Ty::RightParenthesis)?;
+
+                gen::EffectType::Bitcrush(crush)
+            }
+
+            "pan" => {
+                self.eat(Ty::LeftParenthesis)?;
+
+                let mut pan = Pan {
+                    position: self.parse_arg()?,
+                    law: PanLaw::default(),
+                };
+                if let Res::Some(_) = self.eat(Ty::Comma) {
+                    self.parse_options(|p, name| {
+                        match name {
+                            "law" => pan.law = p.parse_named(PanLaw::from_name)?,
+
+                            _ => return Res::Err(ParsErr::UnknownOption(name.to_string())),
+                        }
+
+                        Res::Some(())
+                    })?;
+                }
+                self.eat(Ty::RightParenthesis)?;
+
+                gen::EffectType::Pan(pan)
+            }
+
+            "balance" => {
+                self.eat(Ty::LeftParenthesis)?;
+                let balance = self.parse_arg()?;
+                self.eat(Ty::RightParenthesis)?;
+
+                gen::EffectType::Balance(Balance { balance })
+            }
+
+            "width" => {
+                self.eat(Ty::LeftParenthesis)?;
+                let width = self.parse_arg()?;
+                self.eat(Ty::RightParenthesis)?;
+
+                gen::EffectType::Width(Width::new(width))
+            }
+
+            "gains" => {
+                self.eat(Ty::LeftParenthesis)?;
+                let gains = self.parse_list()?;
+                self.eat(Ty::RightParenthesis)?;
+
+                gen::EffectType::Gains(Gains { gains })
+            }
+
+            _ => {
+                if let Some(kind) = FilterKind::from_name(name) {
+                    self.eat(Ty::LeftParenthesis)?;
+
+                    let mut filter = Filter::new(kind, self.parse_freq()?);
+                    if let Res::Some(_) = self.eat(Ty::Comma) {
+                        self.parse_options(|p, name| {
+                            match name {
+                                "q" => filter.q = p.parse_arg()?,
+                                "gain" => filter.gain = p.parse_arg()?,
+
+                                _ => return Res::Err(ParsErr::UnknownOption(name.to_string())),
+                            }
+
+                            Res::Some(())
+                        })?;
+                    }
+                    self.eat(Ty::RightParenthesis)?;
+
+                    gen::EffectType::Filter(filter)
+                } else if let Some(kind) = DynamicsKind::from_name(name) {
+                    self.eat(Ty::LeftParenthesis)?;
+
+                    let mut dynamics = Dynamics::new(kind);
+                    self.parse_options(|p, name| {
+                        match name {
+                            "threshold" => dynamics.threshold = p.parse_arg()?,
+                            "ratio" => dynamics.ratio = p.parse_arg()?,
+                            "attack" => dynamics.attack = p.parse_duration()?,
+                            "release" => dynamics.release = p.parse_duration()?,
+                            "knee" => dynamics.knee = p.parse_arg()?,
+                            "makeup" => dynamics.makeup = p.parse_arg()?,
+                            "lookahead" | "look_ahead" if kind == DynamicsKind::Limiter => {
+                                dynamics.lookahead = p.parse_duration()?
+                            }
+
+                            _ => return Res::Err(ParsErr::UnknownOption(name.to_string())),
+                        }
+
+                        Res::Some(())
+                    })?;
+                    self.eat(Ty::RightParenthesis)?;
+
+                    gen::EffectType::Dynamics(dynamics)
+                } else if let Some(kind) = ModulationKind::from_name(name) {
+                    self.eat(Ty::LeftParenthesis)?;
+
+                    let mut modulation = Modulation::new(kind);
+                    self.parse_options(|p, name| {
+                        match name {
+                            "rate" => modulation.rate = p.parse_freq()?,
+                            "depth" => modulation.depth = p.parse_arg()?,
+                            "feedback" => modulation.feedback = p.parse_arg()?,
+                            "spread" => modulation.spread = p.parse_arg()?,
+                            "mix" => modulation.mix = p.parse_arg()?,
+
+                            _ => return Res::Err(ParsErr::UnknownOption(name.to_string())),
+                        }
+
+                        Res::Some(())
+                    })?;
+                    self.eat(Ty::RightParenthesis)?;
+
+                    gen::EffectType::Modulation(modulation)
+                } else {
+                    return Res::Err(ParsErr::Unexpected(name_t.ty));
+                }
+            }
         };
 
+        let (start, end) = self.parse_timeframe(parent_len_s)?;
+
         Res::Some(gen::Effect { ty, start, end })
     }
 
+    /// Loads a wav file, relative paths are relative to the song file.
+    fn load_wave(
+        &mut self,
+        at: &Token<'s, S>,
+        path: &str,
+    ) -> Res<wav_reader::Wave, ParsErr<S::Error>> {
+        let song_dir = std::path::Path::new(at.position.source().get_name()).parent();
+        let full_path = match song_dir {
+            Some(dir) => dir.join(path),
+            None => path.into(),
+        };
+
+        match wav_reader::read_file(full_path) {
+            Ok(w) => Res::Some(w),
+            Err(err) => Res::Err(ParsErr::Load {
+                path: path.to_string(),
+                err,
+            }),
+        }
+    }
+
+    /// Parses a frequency expression ending with `hz`.
+    fn parse_freq(&mut self) -> Res<Expression, ParsErr<S::Error>> {
+        self.parse_expression(|t| {
+            if let Some("Hz" | "hz") = t.text() {
+                Terminate::Yes {
+                    discard_token: true,
+                }
+            } else {
+                Terminate::No
+            }
+        })
+    }
+
+    /// Parses a phase expression ending with `rad` or `deg`, in radians.
+    fn parse_phase(&mut self) -> Res<Expression, ParsErr<S::Error>> {
+        let mut rad = false;
+        let phase = self.parse_expression(|t| {
+            if let Some("rad") = t.text() {
+                rad = true;
+                Terminate::Yes {
+                    discard_token: true,
+                }
+            } else if let Some("deg") = t.text() {
+                rad = false;
+                Terminate::Yes {
+                    discard_token: true,
+                }
+            } else {
+                Terminate::No
+            }
+        })?;
+
+        Res::Some(if rad {
+            phase
+        } else {
+            Expression::Mul(
+                phase.into(),
+                Expression::Lit(Number::Real(PI / 180.)).into(),
+            )
+        })
+    }
+
+    /// Parses `[a, b, ...]`.
+    fn parse_list(&mut self) -> Res<Vec<Expression>, ParsErr<S::Error>> {
+        self.eat(Ty::LeftSquareBraces)?;
+
+        let mut items = vec![];
+        loop {
+            if let Res::Some(_) = self.eat(Ty::RightSquareBraces) {
+                break;
+            }
+
+            items.push(self.parse_arg()?);
+
+            if let Res::Err(_) = self.eat(Ty::Comma) {
+                self.eat(Ty::RightSquareBraces)?;
+                break;
+            }
+        }
+
+        Res::Some(items)
+    }
+
+    /// Parses `name [value],` options until something other than a name comes up.
+    ///
+    /// `option` gets the name and has to parse the value (if there is one).
+    fn parse_options<F>(&mut self, mut option: F) -> Res<(), ParsErr<S::Error>>
+    where
+        F: FnMut(&mut Self, &str) -> Res<(), ParsErr<S::Error>>,
+    {
+        loop {
+            let t = self.get_token()?;
+
+            let name = match t.ty {
+                Ty::Identifier => t.text().expect("Couldn't get identifier contents"),
+                Ty::FromKw => "from",
+                Ty::ToKw => "to",
+
+                _ => {
+                    self.buffer.push(t);
+                    break Res::Some(());
+                }
+            };
+
+            option(self, name)?;
+
+            // the last option can go without a comma before a closing parenthesis
+            let t = self.get_token()?;
+            if t.ty != Ty::Comma {
+                self.buffer.push(t);
+                break Res::Some(());
+            }
+        }
+    }
+
+    /// Parses an identifier naming a value, like a curve or a noise color.
+    fn parse_named<T>(&mut self, from_name: fn(&str) -> Option<T>) -> Res<T, ParsErr<S::Error>> {
+        let t = self.eat(Ty::Identifier)?;
+        let name = t.text().expect("Couldn't get identifier contents");
+
+        match from_name(name) {
+            Some(v) => Res::Some(v),
+            None => Res::Err(ParsErr::InvalidOption(name.to_string())),
+        }
+    }
+
+    /// Parses an expression that ends at the next `,` or the `)` closing the argument list.
+    fn parse_arg(&mut self) -> Res<Expression, ParsErr<S::Error>> {
+        let mut depth = 0;
+
+        self.parse_expression(|t| match t.ty {
+            Ty::LeftParenthesis => {
+                depth += 1;
+                Terminate::No
+            }
+            Ty::RightParenthesis if depth > 0 => {
+                depth -= 1;
+                Terminate::No
+            }
+
+            Ty::Comma | Ty::RightParenthesis if depth == 0 => Terminate::Yes {
+                discard_token: false,
+            },
+
+            _ => Terminate::No,
+        })
+    }
+
+    /// Parses an oversampling factor, `1` is none.
+    fn parse_oversampling(&mut self) -> Res<usize, ParsErr<S::Error>> {
+        let factor = self.parse_integer()?;
+
+        match usize::try_from(factor) {
+            Ok(f @ 1..=MAX_OVERSAMPLING) => Res::Some(f),
+            _ => Res::Err(ParsErr::InvalidOption("oversample".to_string())),
+        }
+    }
+
+    fn parse_integer(&mut self) -> Res<i64, ParsErr<S::Error>> {
+        match self.get_token()?.ty {
+            Ty::NumberLiteral(Number::Integer(i)) => Res::Some(i),
+
+            ty => Res::Err(ParsErr::Unexpected(ty)),
+        }
+    }
+
+    /// Parses a number with an optional time unit into seconds.
+    fn parse_duration(&mut self) -> Res<f64, ParsErr<S::Error>> {
+        let n: f64 = match self.get_token()?.ty {
+            Ty::NumberLiteral(n) => n.into(),
+
+            ty => return Res::Err(ParsErr::Unexpected(ty)),
+        };
+
+        Res::Some(match self.parse_time_unit() {
+            Res::Some(u) => n * u,
+            _ => n,
+        })
+    }
+
     fn parse_chan(&mut self) -> Res<Channels, ParsErr<S::Error>> {
         self.eat(Ty::OnKw)?;
 
@@ -385,7 +1168,9 @@ impl<'d, 's, S: Source> Parser<'d, 's, S> {
             Ty::LeftParenthesis => 0,
             Ty::Plus | Ty::Minus => 1,
             Ty::Slash | Ty::Star | Ty::Percent => 2,
-            Ty::Caret => 3,
+            // -a^b is -(a^b)
+            Ty::Negate => 3,
+            Ty::Caret => 4,
 
             _ => unreachable!(),
         };
@@ -398,7 +1183,7 @@ impl<'d, 's, S: Source> Parser<'d, 's, S> {
         }
         let assoc = |t: &Token<'s, S>| match t.ty {
             Ty::LeftParenthesis | Ty::RightParenthesis => Assoc::NonAssoc,
-            Ty::Caret => Assoc::Right,
+            Ty::Caret | Ty::Negate => Assoc::Right,
 
             Ty::Slash | Ty::Star | Ty::Percent | Ty::Plus | Ty::Minus => Assoc::Left,
 
@@ -408,6 +1193,9 @@ impl<'d, 's, S: Source> Parser<'d, 's, S> {
         // shunting yard algorithm
         // from https://en.wikipedia.org/wiki/Shunting_yard_algorithm#The_algorithm_in_detail
 
+        // a minus at the start, after an operator or a parenthesis is a sign
+        let mut expect_operand = true;
+
         // while there are tokens to be read:
         //     read a token
         while let Res::Some(t) = self.get_token() {
@@ -418,7 +1206,21 @@ impl<'d, 's, S: Source> Parser<'d, 's, S> {
                 break;
             }
 
+            let operand_next = match t.ty {
+                Ty::Plus | Ty::Minus | Ty::Star | Ty::Slash | Ty::Caret | Ty::Percent => true,
+                Ty::LeftParenthesis | Ty::Comma => true,
+                Ty::Identifier => MathFunc::is_func(t.position.get_text().unwrap()),
+
+                _ => false,
+            };
+
             match t.ty {
+                // a prefix operator, nothing before it is its operand
+                Ty::Minus if expect_operand => ops.push(Token {
+                    ty: Ty::Negate,
+                    ..t
+                }),
+
                 // if the token is:
                 // - a number:
                 //     put it into the output queue
@@ -445,7 +1247,7 @@ impl<'d, 's, S: Source> Parser<'d, 's, S> {
                             break 'w false;
                         };
 
-                        if o2.ty != Ty::LeftParenthesis {
+                        if o2.ty == Ty::LeftParenthesis {
                             break 'w false;
                         }
 
@@ -505,12 +1307,11 @@ impl<'d, 's, S: Source> Parser<'d, 's, S> {
                     }
 
                     // if there is a function token at the top of the operator stack, then:
-                    if !ops.is_empty()
-                        && ops.last().unwrap().ty == Ty::Identifier
-                        && MathFunc::is_func(t.position.get_text().unwrap())
-                    {
-                        // pop the function from the operator stack into the output queue
-                        output_queue.push(ops.pop().unwrap());
+                    // pop the function from the operator stack into the output queue
+                    if let Some(f) = ops.pop_if(|f| {
+                        f.ty == Ty::Identifier && MathFunc::is_func(f.position.get_text().unwrap())
+                    }) {
+                        output_queue.push(f);
                     }
                 }
 
@@ -519,6 +1320,8 @@ impl<'d, 's, S: Source> Parser<'d, 's, S> {
                     break;
                 }
             }
+
+            expect_operand = operand_next;
         }
 
         // /* After the while loop, pop the remaining items from the operator stack into the output queue. */
@@ -571,6 +1374,7 @@ pub enum Expression {
     Pow(Box<Expression>, Box<Expression>),
     Mod(Box<Expression>, Box<Expression>),
 
+    Neg(Box<Expression>),
     Call(MathFunc, Box<Expression>),
 
     VarOrConst(String),
@@ -584,6 +1388,9 @@ pub enum ExpressionError {
 
     #[error("No GenInfo")]
     NoGenInfo,
+
+    #[error("x is only known in a waveshaper")]
+    NoInput,
 }
 
 impl Expression {
@@ -599,6 +1406,7 @@ impl Expression {
 
             Self::Pow(b, a) => a.evaluate(gi)?.powf(b.evaluate(gi)?),
 
+            Self::Neg(a) => -a.evaluate(gi)?,
             Self::Call(f, arg) => f.call(arg.evaluate(gi)?),
 
             Self::VarOrConst(name) => match &name[..] {
@@ -607,6 +1415,13 @@ impl Expression {
 
                 "channel" | "ch" => gi.ok_or(ExpressionError::NoGenInfo)?.channel as f64,
                 "t" => gi.ok_or(ExpressionError::NoGenInfo)?.t,
+                "secs" => gi.ok_or(ExpressionError::NoGenInfo)?.secs(),
+                "song_t" => gi.ok_or(ExpressionError::NoGenInfo)?.song_t,
+                "song_secs" => gi.ok_or(ExpressionError::NoGenInfo)?.song_secs,
+                "x" => gi
+                    .ok_or(ExpressionError::NoGenInfo)?
+                    .input
+                    .ok_or(ExpressionError::NoInput)?,
 
                 v => return Err(ExpressionError::UnknownVar(v.to_string())),
             },
@@ -655,6 +1470,11 @@ impl Expression {
                 Box::new(Self::construct(iter)),
             ),
 
+            Ty::Negate => match Self::construct(iter) {
+                Self::Lit(n) => Self::Lit(-n),
+                a => Self::Neg(Box::new(a)),
+            },
+
             Ty::Comma => todo!(),
 
             Ty::RightParenthesis | Ty::LeftParenthesis => Self::construct(iter),
@@ -683,6 +1503,12 @@ pub enum MathFunc {
     Ceil,
     Rad,
     Deg,
+    Tanh,
+    Atan,
+    /// The curves of `drive`, for waveshapers.
+    Clip,
+    Fold,
+    Asym,
 }
 
 impl MathFunc {
@@ -701,6 +1527,11 @@ impl MathFunc {
             Self::Ceil => x.ceil(),
             Self::Rad => x.to_radians(),
             Self::Deg => x.to_degrees(),
+            Self::Tanh => x.tanh(),
+            Self::Atan => x.atan(),
+            Self::Clip => Curve::Hard.shape(x),
+            Self::Fold => Curve::Fold.shape(x),
+            Self::Asym => Curve::Asymmetric.shape(x),
         }
     }
 
@@ -727,6 +1558,11 @@ impl FromStr for MathFunc {
             "ceil" => MathFunc::Ceil,
             "rad" => MathFunc::Rad,
             "deg" => MathFunc::Deg,
+            "tanh" => MathFunc::Tanh,
+            "atan" => MathFunc::Atan,
+            "clip" => MathFunc::Clip,
+            "fold" => MathFunc::Fold,
+            "asym" => MathFunc::Asym,
 
             _ => return Err(()),
         })
@@ -749,6 +1585,11 @@ impl Display for MathFunc {
             MathFunc::Ceil => write!(f, "ceil"),
             MathFunc::Rad => write!(f, "rad"),
             MathFunc::Deg => write!(f, "deg"),
+            MathFunc::Tanh => write!(f, "tanh"),
+            MathFunc::Atan => write!(f, "atan"),
+            MathFunc::Clip => write!(f, "clip"),
+            MathFunc::Fold => write!(f, "fold"),
+            MathFunc::Asym => write!(f, "asym"),
         }
     }
 }
@@ -762,6 +1603,7 @@ impl Display for Expression {
             Expression::Div(a, b) => write!(f, "{a} / {b}"),
             Expression::Pow(a, b) => write!(f, "{a}^{b}"),
             Expression::Mod(a, b) => write!(f, "{a} % {b}"),
+            Expression::Neg(a) => write!(f, "-{a}"),
             Expression::Call(a, b) => write!(f, "{a}({b})"),
             Expression::VarOrConst(a) => write!(f, "{a}"),
             Expression::Lit(a) => write!(f, "{a}"),
@@ -774,3 +1616,87 @@ pub enum Terminate {
     Yes { discard_token: bool },
     No,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{get_song, source::StringSource, tokenizer::Tokenizer, Parser, ParserError, Res};
+    use crate::gen::GenInfo;
+
+    /// Evaluates an expression with `t`, `x` and every other time set to `t`.
+    fn evaluate(src: &str, t: f64) -> f64 {
+        let mut diagnostics = vec![];
+        let tokenizer = Tokenizer::new(StringSource::new("test", src), &mut diagnostics);
+
+        let Res::Some(expression) = Parser::new(tokenizer).parse_arg() else {
+            panic!("{src} didn't parse");
+        };
+
+        let gi = GenInfo {
+            channel: 0,
+            channels: 1,
+            samplerate: 44100,
+            t,
+            len_s: 1.,
+            song_t: t,
+            song_secs: t,
+            input: Some(t),
+        };
+        expression.evaluate(Some(gi)).unwrap()
+    }
+
+    fn fm_error(options: &str) -> Option<String> {
+        let src = format!("\"t\" 1s on 1 fm(carrier 440 hz, {options}, :) on * @ 1 {{}}");
+
+        match get_song("test", &src) {
+            Err(ParserError::InvalidOption(o)) => Some(o),
+            Err(e) => panic!("{options}: {e}"),
+            Ok(_) => None,
+        }
+    }
+
+    #[test]
+    fn fm_algorithms_are_checked() {
+        assert_eq!(fm_error("algo 1"), None);
+        assert_eq!(fm_error("algo 0").as_deref(), Some("algo"));
+        assert_eq!(fm_error("algo 1000").as_deref(), Some("algo"));
+    }
+
+    #[test]
+    fn fm_modulator_needs_two_operators() {
+        assert_eq!(fm_error("ratios [1, 2], ratio 3"), None);
+        assert_eq!(fm_error("ratios [1], ratio 3").as_deref(), Some("ratio"));
+        assert_eq!(fm_error("indices [1], index 3").as_deref(), Some("index"));
+    }
+
+    #[test]
+    fn minus_negates_any_operand() {
+        for (src, expected) in [
+            ("-t", -0.25),
+            ("-x", -0.25),
+            ("-(1 + t)", -1.25),
+            ("-sin(t)", -f64::sin(0.25)),
+            ("2 * -t", -0.5),
+            ("-t + 1", 0.75),
+            ("1 - -t", 1.25),
+            ("-2^2", -4.),
+            ("2^-1", 0.5),
+        ] {
+            let v = evaluate(src, 0.25);
+            assert!((v - expected).abs() < 1e-12, "{src} = {v}");
+        }
+    }
+
+    #[test]
+    fn operators_have_precedence() {
+        for (src, expected) in [
+            ("3 - 2 - 1", 0.),
+            ("2 * 3 + 4", 10.),
+            ("4 + 2 * 3", 10.),
+            ("2^3^2", 512.),
+            ("sin(t) * 2", f64::sin(0.25) * 2.),
+        ] {
+            let v = evaluate(src, 0.25);
+            assert!((v - expected).abs() < 1e-12, "{src} = {v}");
+        }
+    }
+}
@@ -73,6 +73,7 @@ impl<'s, S: Source> std::fmt::Display for Token<'s, S> {
             DoubleMinus => write!(f, "--"),
             Tilda => write!(f, "~"),
             Bang => write!(f, "!"),
+            Negate => write!(f, "-"),
             BangEquals => write!(f, "!="),
             RightShift => write!(f, ">>"),
             LesserThan => write!(f, "<"),
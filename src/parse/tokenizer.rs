@@ -27,6 +27,16 @@ impl From<f64> for Number {
         Self::Real(value)
     }
 }
+impl std::ops::Neg for Number {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        match self {
+            Number::Integer(i) => Number::Integer(-i),
+            Number::Real(r) => Number::Real(-r),
+        }
+    }
+}
 impl Display for Number {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -60,6 +70,8 @@ pub enum TokenType {
     DoubleMinus,
     Tilda,
     Bang,
+    /// A minus in front of an operand, the expression parser makes these out of minuses.
+    Negate,
     // unary
 
     // binary
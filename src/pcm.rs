@@ -1,33 +1,292 @@
+use std::io::{Seek, Write};
+
+use thiserror::Error as ThisError;
+
 use crate::{
     gen::{self, GenInfo, Song},
+    master::{Levels, Master},
     parse,
+    wav::{SampleFormat, WaveDesc, WaveWriter},
 };
 
-pub fn generate_pcm(song: &mut Song, samplerate: usize) -> Result<Vec<u8>, parse::ExpressionError> {
-    const BYTES_PER_SAMPLE: usize = std::mem::size_of::<i16>();
+/// Frames rendered per block when streaming.
+pub const BLOCK_FRAMES: usize = 4096;
+
+#[derive(Debug, ThisError)]
+pub enum RenderError {
+    #[error(transparent)]
+    Expression(#[from] parse::ExpressionError),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Pulls interleaved samples from a [`Song`] block by block.
+pub struct Renderer<'s> {
+    song: &'s mut Song,
+    samplerate: usize,
+
+    frame: usize,
+    frames: usize,
+}
+
+impl<'s> Renderer<'s> {
+    pub fn new(song: &'s mut Song, samplerate: usize) -> Self {
+        let frames = (samplerate as f64 * song.length_s) as usize;
+
+        Self {
+            song,
+            samplerate,
+            frame: 0,
+            frames,
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.song.channels
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.frames - self.frame
+    }
+
+    /// Fills `out` with as many whole frames as fit (or are left), returns the number of frames rendered.
+    pub fn render_block(&mut self, out: &mut [f64]) -> Result<usize, parse::ExpressionError> {
+        let channels = self.song.channels;
+        let n = (out.len() / channels).min(self.remaining());
+
+        for frame in out.chunks_exact_mut(channels).take(n) {
+            let secs = self.frame as f64 / self.samplerate as f64;
+
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let gi = GenInfo::for_song(self.song, channel, self.samplerate, secs);
+
+                *sample = gen::get_sample(self.song, gi)?;
+            }
 
-    let samples = (samplerate as f64 * song.length_s) as usize;
+            self.frame += 1;
+        }
+
+        Ok(n)
+    }
+}
 
-    let mut data = vec![0; samples * song.channels * BYTES_PER_SAMPLE];
-    for i in 0..samples {
-        let offs = i * song.channels * BYTES_PER_SAMPLE;
-        let t = i as f64 / samplerate as f64;
+/// First pass of a normalized render, the song's state is used up afterwards.
+pub fn measure(song: &mut Song, samplerate: usize) -> Result<Levels, parse::ExpressionError> {
+    let mut renderer = Renderer::new(song, samplerate);
+    let channels = renderer.channels();
 
-        for channel in 0..song.channels {
-            let gi = GenInfo {
-                channel,
-                t: t / song.length_s,
-            };
+    let mut levels = Levels::new(channels, samplerate);
+    let mut block = vec![0.; BLOCK_FRAMES * channels];
 
-            let sample = gen::get_sample(song, gi)?;
-            let sample = (sample * i16::MAX as f64) as i16;
+    loop {
+        let n = renderer.render_block(&mut block)?;
+        if n == 0 {
+            break Ok(levels);
+        }
+
+        for frame in block[..n * channels].chunks_exact(channels) {
+            levels.add(frame);
+        }
+    }
+}
+
+fn encode(format: SampleFormat, samples: &[f64], out: &mut Vec<u8>) {
+    out.resize(samples.len() * format.bytes(), 0);
+
+    for (s, o) in samples.iter().zip(out.chunks_exact_mut(format.bytes())) {
+        format.encode(*s, o);
+    }
+}
 
-            let data_start = offs + channel * BYTES_PER_SAMPLE;
-            let data_end = data_start + BYTES_PER_SAMPLE;
+/// Renders, masters and encodes the song block by block, handing every block to `out`.
+fn render_blocks<E: From<parse::ExpressionError>>(
+    renderer: &mut Renderer,
+    format: SampleFormat,
+    master: &mut Master,
+    mut out: impl FnMut(&[u8]) -> Result<(), E>,
+) -> Result<(), E> {
+    let mut block = vec![0.; BLOCK_FRAMES * renderer.channels()];
+    let mut bytes = vec![];
 
-            data[data_start..data_end].copy_from_slice(&sample.to_le_bytes());
+    loop {
+        let n = renderer.render_block(&mut block)?;
+        if n == 0 {
+            break Ok(());
         }
+
+        let samples = &mut block[..n * renderer.channels()];
+        master.process(samples, renderer.channels());
+        encode(format, samples, &mut bytes);
+        out(&bytes)?;
     }
+}
+
+/// Renders the whole song into memory, as samples in `format`.
+pub fn generate_pcm(
+    song: &mut Song,
+    samplerate: usize,
+    format: SampleFormat,
+    master: &mut Master,
+) -> Result<Vec<u8>, parse::ExpressionError> {
+    let mut renderer = Renderer::new(song, samplerate);
+
+    let mut data = Vec::with_capacity(renderer.frames() * renderer.channels() * format.bytes());
+    render_blocks(&mut renderer, format, master, |bytes| {
+        data.extend_from_slice(bytes);
+        Ok::<_, parse::ExpressionError>(())
+    })?;
 
     Ok(data)
 }
+
+fn wave_writer<W: Write>(
+    renderer: &Renderer,
+    samplerate: usize,
+    format: SampleFormat,
+    w: W,
+) -> std::io::Result<WaveWriter<W>> {
+    let desc = WaveDesc::from_data(renderer.channels() as u16, samplerate as u32, format);
+    let data_len = (renderer.frames() * desc.block_align()) as u64;
+
+    WaveWriter::new(desc, data_len, w)
+}
+
+/// Renders the song into a seekable writer, patching the header at the end.
+pub fn write_wav<W: Write + Seek>(
+    song: &mut Song,
+    samplerate: usize,
+    format: SampleFormat,
+    master: &mut Master,
+    w: W,
+) -> Result<(), RenderError> {
+    let mut renderer = Renderer::new(song, samplerate);
+    let mut writer = wave_writer(&renderer, samplerate, format, w)?;
+
+    render_blocks(&mut renderer, format, master, |bytes| {
+        writer.write_data(bytes).map_err(RenderError::from)
+    })?;
+
+    writer.finish()?;
+    Ok(())
+}
+
+/// Renders the song into a writer that can't seek (a pipe, stdout), using a precomputed header.
+pub fn stream_wav<W: Write>(
+    song: &mut Song,
+    samplerate: usize,
+    format: SampleFormat,
+    master: &mut Master,
+    w: W,
+) -> Result<(), RenderError> {
+    let mut renderer = Renderer::new(song, samplerate);
+    let mut writer = wave_writer(&renderer, samplerate, format, w)?;
+
+    render_blocks(&mut renderer, format, master, |bytes| {
+        writer.write_data(bytes).map_err(RenderError::from)
+    })?;
+
+    writer.finish_streamed()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{generate_pcm, stream_wav};
+    use crate::{
+        master::{Dither, Master, MasterOptions},
+        parse,
+        wav::{reader::read_header, SampleFormat},
+    };
+
+    #[test]
+    fn pcm_is_in_the_chosen_format() {
+        // a steady 1 (the first half of a slow square), then a steady -1
+        let src = "\"t\" 1s on 1 square(1 hz, 0 rad, naive, :) on * @ 0.5";
+        let options = MasterOptions {
+            dither: Dither::Off,
+            ..Default::default()
+        };
+
+        for (format, high, low) in [
+            (SampleFormat::Int(8), vec![192], vec![64]),
+            (
+                SampleFormat::Int(16),
+                16384i16.to_le_bytes().to_vec(),
+                (-16384i16).to_le_bytes().to_vec(),
+            ),
+            (
+                SampleFormat::Int(24),
+                4194304i32.to_le_bytes()[..3].to_vec(),
+                (-4194304i32).to_le_bytes()[..3].to_vec(),
+            ),
+            (
+                SampleFormat::Float(32),
+                0.5f32.to_le_bytes().to_vec(),
+                (-0.5f32).to_le_bytes().to_vec(),
+            ),
+            (
+                SampleFormat::Float(64),
+                0.5f64.to_le_bytes().to_vec(),
+                (-0.5f64).to_le_bytes().to_vec(),
+            ),
+        ] {
+            let mut song = parse::get_song("test", src).unwrap();
+            let mut master = Master::new(options, format);
+            let pcm = generate_pcm(&mut song, 1000, format, &mut master).unwrap();
+
+            let samples: Vec<&[u8]> = pcm.chunks_exact(format.bytes()).collect();
+            assert_eq!(samples.len(), 1000, "{format}");
+            assert_eq!(samples[100], high, "{format}");
+            assert_eq!(samples[900], low, "{format}");
+        }
+    }
+
+    #[test]
+    fn streams_hold_what_the_header_says() {
+        /// Stdout, it can't seek.
+        struct Pipe(Vec<u8>);
+
+        impl Write for Pipe {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        // an odd number of bytes, so the data chunk needs a pad byte
+        let src = "\"t\" 1s on 1 sine(440 hz, 0 rad, :) on * @ 0.5";
+        let format = SampleFormat::Int(8);
+        let options = MasterOptions {
+            dither: Dither::Off,
+            ..Default::default()
+        };
+
+        let mut song = parse::get_song("test", src).unwrap();
+        let pcm =
+            generate_pcm(&mut song, 11025, format, &mut Master::new(options, format)).unwrap();
+
+        let mut song = parse::get_song("test", src).unwrap();
+        let mut pipe = Pipe(vec![]);
+        let mut master = Master::new(options, format);
+        stream_wav(&mut song, 11025, format, &mut master, &mut pipe).unwrap();
+        let file = pipe.0;
+
+        let header = read_header(&mut &file[..]).unwrap();
+        assert_eq!(header.riff_size + 8, file.len() as u64);
+        assert_eq!(header.data_size, 11025);
+
+        let data = &file[file.len() - 11026..];
+        assert_eq!(&data[..11025], &pcm[..]);
+        assert_eq!(data[11025], 0);
+    }
+}
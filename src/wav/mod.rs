@@ -0,0 +1,485 @@
+use std::io::{Seek, SeekFrom, Write};
+
+pub mod reader;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+    Int(u16),
+    Float(u16),
+}
+
+impl SampleFormat {
+    pub fn new(bits: u16, float: bool) -> Option<Self> {
+        match (bits, float) {
+            (8 | 16 | 24 | 32, false) => Some(Self::Int(bits)),
+            (32 | 64, true) => Some(Self::Float(bits)),
+
+            _ => None,
+        }
+    }
+
+    pub fn bits(&self) -> u16 {
+        match *self {
+            Self::Int(b) | Self::Float(b) => b,
+        }
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bits() as usize / 8
+    }
+
+    pub fn format_tag(&self) -> u16 {
+        match self {
+            Self::Int(_) => 1,
+            Self::Float(_) => 3,
+        }
+    }
+
+    /// Largest integer sample value, full scale maps to it. `None` for floats.
+    pub fn full_scale(&self) -> Option<f64> {
+        match *self {
+            Self::Int(b) => Some(((1u64 << (b - 1)) - 1) as f64),
+            Self::Float(_) => None,
+        }
+    }
+
+    /// Quantizes `v` (nominally in -1..=1) into `out`, which has to be `self.bytes()` long.
+    /// Integers are rounded to the nearest step and saturate at full scale.
+    pub fn encode(&self, v: f64, out: &mut [u8]) {
+        let int = |max: f64| (v.clamp(-1., 1.) * max).round();
+
+        match *self {
+            // 8 bit wav is unsigned
+            Self::Int(8) => out[0] = (int(i8::MAX as f64) + 128.) as u8,
+            Self::Int(16) => out.copy_from_slice(&(int(i16::MAX as f64) as i16).to_le_bytes()),
+            Self::Int(24) => {
+                const I24_MAX: f64 = ((1 << 23) - 1) as f64;
+
+                out.copy_from_slice(&(int(I24_MAX) as i32).to_le_bytes()[..3]);
+            }
+            Self::Int(32) => out.copy_from_slice(&(int(i32::MAX as f64) as i32).to_le_bytes()),
+
+            Self::Float(32) => out.copy_from_slice(&(v as f32).to_le_bytes()),
+            Self::Float(64) => out.copy_from_slice(&v.to_le_bytes()),
+
+            f => unreachable!("invalid sample format {f}"),
+        }
+    }
+}
+
+impl std::fmt::Display for SampleFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SampleFormat::Int(b) => write!(f, "{b} bit"),
+            SampleFormat::Float(b) => write!(f, "{b} bit float"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaveDesc {
+    channels: u16,
+    samplerate: u32,
+    format: SampleFormat,
+}
+
+impl WaveDesc {
+    pub fn from_data(channels: u16, samplerate: u32, format: SampleFormat) -> Self {
+        Self {
+            channels,
+            samplerate,
+            format,
+        }
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+    pub fn samplerate(&self) -> u32 {
+        self.samplerate
+    }
+    pub fn format(&self) -> SampleFormat {
+        self.format
+    }
+
+    fn is_pcm(&self) -> bool {
+        self.format.format_tag() == 1
+    }
+
+    /// Bytes per frame, can be more than the 16 bit field in the header holds.
+    pub fn block_align(&self) -> usize {
+        self.channels as usize * self.format.bytes()
+    }
+
+    /// Size of everything in front of the sample data.
+    pub fn header_len(&self, ds64: bool) -> u64 {
+        // non-pcm formats need the cbSize field and a fact chunk
+        let fmt = if self.is_pcm() {
+            8 + 16
+        } else {
+            (8 + 18) + (8 + 4)
+        };
+
+        12 + if ds64 { 8 + DS64_SIZE as u64 } else { 0 } + fmt + 8
+    }
+
+    pub fn needs_rf64(&self, data_len: u64) -> bool {
+        // the riff size doesn't count the first 8 bytes, but does count the padding
+        self.header_len(true) - 8 + data_len + (data_len & 1) > u32::MAX as u64
+    }
+
+    /// With `reserve_ds64` files that fit in RIFF get a JUNK chunk the size of a ds64 one,
+    /// so the header can later be rewritten as RF64 in place.
+    pub fn write_header(
+        &self,
+        data_len: u64,
+        reserve_ds64: bool,
+        mut w: impl Write,
+    ) -> std::io::Result<()> {
+        let rf64 = self.needs_rf64(data_len);
+        let ds64 = rf64 || reserve_ds64;
+
+        let bits_per_sample = self.format.bits();
+        let block_align = u16::try_from(self.block_align()).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "too many channels")
+        })?;
+        let byterate =
+            u32::try_from(self.samplerate as u64 * block_align as u64).unwrap_or(u32::MAX);
+
+        let frames = data_len / block_align as u64;
+
+        let subchunk1_size: u32 = if self.is_pcm() { 16 } else { 18 };
+
+        // the data chunk is padded to an even length
+        let chunk_size = self.header_len(ds64) - 8 + data_len + (data_len & 1);
+
+        // in RF64 files the real sizes live in the ds64 chunk
+        let size32 = |s: u64| if rf64 { u32::MAX } else { s as u32 };
+
+        // ---------- RIFF descriptor ----------
+        w.write_all(if rf64 { b"RF64" } else { b"RIFF" })?;
+
+        w.write_all(&size32(chunk_size).to_le_bytes())?;
+        w.write_all(b"WAVE")?;
+
+        // ---------- ds64 chunk ----------
+        if ds64 {
+            w.write_all(if rf64 { b"ds64" } else { b"JUNK" })?;
+            w.write_all(&DS64_SIZE.to_le_bytes())?;
+
+            if rf64 {
+                w.write_all(&chunk_size.to_le_bytes())?;
+                w.write_all(&data_len.to_le_bytes())?;
+                w.write_all(&frames.to_le_bytes())?;
+
+                // table length
+                w.write_all(&0u32.to_le_bytes())?;
+            } else {
+                w.write_all(&[0; DS64_SIZE as usize])?;
+            }
+        }
+
+        // ---------- fmt chunk ----------
+        w.write_all(b"fmt ")?;
+
+        w.write_all(&subchunk1_size.to_le_bytes())?;
+
+        w.write_all(&self.format.format_tag().to_le_bytes())?;
+        w.write_all(&self.channels.to_le_bytes())?;
+
+        w.write_all(&self.samplerate.to_le_bytes())?;
+        w.write_all(&byterate.to_le_bytes())?;
+        w.write_all(&block_align.to_le_bytes())?;
+        w.write_all(&bits_per_sample.to_le_bytes())?;
+
+        if !self.is_pcm() {
+            // cbSize
+            w.write_all(&0u16.to_le_bytes())?;
+
+            // ---------- fact chunk ----------
+            w.write_all(b"fact")?;
+            w.write_all(&4u32.to_le_bytes())?;
+            w.write_all(&size32(frames).to_le_bytes())?;
+        }
+
+        // ---------- data chunk ----------
+        w.write_all(b"data")?;
+        w.write_all(&size32(data_len).to_le_bytes())
+    }
+
+    pub fn write(&self, data: &[u8], mut w: impl Write) -> std::io::Result<()> {
+        self.write_header(data.len() as u64, false, &mut w)?;
+        w.write_all(data)?;
+
+        if data.len() & 1 == 1 {
+            w.write_all(&[0])?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Size of the ds64 chunk body: riff size, data size, sample count and an empty table.
+const DS64_SIZE: u32 = 8 + 8 + 8 + 4;
+
+/// Writes a wav file incrementally.
+///
+/// The header is written up front with the expected data length. Seekable
+/// writers get the real sizes patched in by [`WaveWriter::finish`], anything
+/// else has to use [`WaveWriter::finish_streamed`], which pads the data to the
+/// promised length.
+pub struct WaveWriter<W> {
+    desc: WaveDesc,
+    expected: u64,
+    written: u64,
+    w: W,
+}
+
+impl<W: Write> WaveWriter<W> {
+    pub fn new(desc: WaveDesc, expected_data_len: u64, mut w: W) -> std::io::Result<Self> {
+        desc.write_header(expected_data_len, true, &mut w)?;
+
+        Ok(Self {
+            desc,
+            expected: expected_data_len,
+            written: 0,
+            w,
+        })
+    }
+
+    pub fn desc(&self) -> &WaveDesc {
+        &self.desc
+    }
+
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    pub fn write_data(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.w.write_all(data)?;
+        self.written += data.len() as u64;
+
+        Ok(())
+    }
+
+    fn write_padding(&mut self, mut len: u64, byte: u8) -> std::io::Result<()> {
+        let fill = [byte; 4096];
+
+        while len > 0 {
+            let n = len.min(fill.len() as u64) as usize;
+            self.w.write_all(&fill[..n])?;
+            len -= n as u64;
+        }
+
+        Ok(())
+    }
+
+    /// Finishes a file whose header can't be rewritten.
+    pub fn finish_streamed(mut self) -> std::io::Result<W> {
+        if self.written > self.expected {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "wrote more data than the header announced",
+            ));
+        }
+
+        // 8 bit samples are unsigned, their silence is 128
+        let silence = if self.desc.format == SampleFormat::Int(8) {
+            0x80
+        } else {
+            0
+        };
+        self.write_padding(self.expected - self.written, silence)?;
+        self.write_padding(self.expected & 1, 0)?;
+
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+impl<W: Write + Seek> WaveWriter<W> {
+    /// Finishes the file and patches the header with the real sizes.
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.write_padding(self.written & 1, 0)?;
+
+        self.w.seek(SeekFrom::Start(0))?;
+        self.desc.write_header(self.written, true, &mut self.w)?;
+        self.w.seek(SeekFrom::End(0))?;
+
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+pub fn write_to_wav(
+    channels: usize,
+    sample_rate: usize,
+    format: SampleFormat,
+    data: &[u8],
+    w: impl Write,
+) -> Result<(), std::io::Error> {
+    let desc = &WaveDesc::from_data(channels as u16, sample_rate as u32, format);
+
+    desc.write(data, w)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Seek, SeekFrom, Write};
+
+    use super::{
+        reader::{read, read_header},
+        SampleFormat, WaveDesc, WaveWriter,
+    };
+
+    /// Keeps the start of the file and only counts the rest.
+    #[derive(Default)]
+    struct HeaderSink {
+        head: Vec<u8>,
+        pos: u64,
+        len: u64,
+    }
+
+    impl HeaderSink {
+        const KEEP: u64 = 256;
+    }
+
+    impl Write for HeaderSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            for (i, b) in buf.iter().enumerate() {
+                let p = self.pos + i as u64;
+                if p >= Self::KEEP {
+                    break;
+                }
+
+                if p as usize >= self.head.len() {
+                    self.head.resize(p as usize + 1, 0);
+                }
+                self.head[p as usize] = *b;
+            }
+
+            self.pos += buf.len() as u64;
+            self.len = self.len.max(self.pos);
+
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for HeaderSink {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.pos = match pos {
+                SeekFrom::Start(p) => p,
+                SeekFrom::End(p) => self.len.checked_add_signed(p).unwrap(),
+                SeekFrom::Current(p) => self.pos.checked_add_signed(p).unwrap(),
+            };
+
+            Ok(self.pos)
+        }
+    }
+
+    fn render(desc: WaveDesc, expected_len: u64, data_len: u64) -> HeaderSink {
+        let mut w = WaveWriter::new(desc, expected_len, HeaderSink::default()).unwrap();
+
+        let block = vec![0; 1 << 20];
+        let mut left = data_len;
+        while left > 0 {
+            let n = left.min(block.len() as u64);
+            w.write_data(&block[..n as usize]).unwrap();
+            left -= n;
+        }
+
+        w.finish().unwrap()
+    }
+
+    #[test]
+    fn small_files_stay_riff() {
+        let desc = WaveDesc::from_data(2, 44100, SampleFormat::Int(16));
+        let data = vec![0; 4000];
+
+        let mut c = Cursor::new(vec![]);
+        desc.write(&data, &mut c).unwrap();
+        let file = c.into_inner();
+
+        let header = read_header(&mut &file[..]).unwrap();
+        assert_eq!(&file[..4], b"RIFF");
+        assert_eq!(header.desc, desc);
+        assert_eq!(header.riff_size + 8, file.len() as u64);
+        assert_eq!(header.data_size, data.len() as u64);
+
+        let sink = render(desc, 4001, 4001);
+        let header = read_header(&mut &sink.head[..]).unwrap();
+        assert_eq!(&sink.head[..4], b"RIFF");
+        assert_eq!(header.riff_size + 8, sink.len);
+        assert_eq!(header.data_size, 4001);
+    }
+
+    #[test]
+    fn big_files_become_rf64() {
+        let desc = WaveDesc::from_data(8, 192000, SampleFormat::Float(32));
+        let data_len = u32::MAX as u64 + 1234 * 32;
+
+        let sink = render(desc, data_len, data_len);
+        let header = read_header(&mut &sink.head[..]).unwrap();
+
+        assert_eq!(&sink.head[..4], b"RF64");
+        assert_eq!(header.desc, desc);
+        assert_eq!(header.riff_size + 8, sink.len);
+        assert_eq!(header.data_size, data_len);
+    }
+
+    #[test]
+    fn rf64_when_the_expected_size_was_too_small() {
+        let desc = WaveDesc::from_data(1, 48000, SampleFormat::Int(32));
+        let data_len = u32::MAX as u64 + 4;
+
+        let sink = render(desc, 1000, data_len);
+        let header = read_header(&mut &sink.head[..]).unwrap();
+        assert_eq!(&sink.head[..4], b"RF64");
+        assert_eq!(header.riff_size + 8, sink.len);
+        assert_eq!(header.data_size, data_len);
+    }
+
+    #[test]
+    fn streams_are_padded_with_silence() {
+        /// A pipe, it can't seek.
+        struct Pipe(Vec<u8>);
+
+        impl Write for Pipe {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        for format in [SampleFormat::Int(8), SampleFormat::Int(16)] {
+            let desc = WaveDesc::from_data(1, 8000, format);
+            let expected = 1001 * format.bytes() as u64;
+
+            let mut w = WaveWriter::new(desc, expected, Pipe(vec![])).unwrap();
+            let mut sample = vec![0; format.bytes()];
+            format.encode(0.5, &mut sample);
+            for _ in 0..600 {
+                w.write_data(&sample).unwrap();
+            }
+            let file = w.finish_streamed().unwrap().0;
+
+            let header = read_header(&mut &file[..]).unwrap();
+            assert_eq!(header.riff_size + 8, file.len() as u64, "{format}");
+            assert_eq!(header.data_size, expected, "{format}");
+
+            let wave = read(Cursor::new(file)).unwrap();
+            assert_eq!(wave.frames(), 1001, "{format}");
+            for i in 0..1001 {
+                let expected = if i < 600 { 0.5 } else { 0. };
+                assert!((wave.get(i, 0) - expected).abs() < 0.01, "{format}: {i}");
+            }
+        }
+    }
+}
@@ -0,0 +1,472 @@
+use std::{io::Read, path::Path};
+
+use thiserror::Error as ThisError;
+
+use super::{SampleFormat, WaveDesc};
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Everything but the first two bytes of the KSDATAFORMAT_SUBTYPE_* GUIDs.
+const SUBFORMAT_GUID_TAIL: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+/// Even WAVE_FORMAT_EXTENSIBLE only needs 40 bytes, anything much bigger is garbage.
+const MAX_FMT_SIZE: u64 = 1024;
+
+#[derive(Debug, ThisError)]
+pub enum WavError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error("Not a RIFF/WAVE file")]
+    NotWave,
+
+    #[error("Missing fmt chunk")]
+    MissingFmt,
+
+    #[error("Missing data chunk")]
+    MissingData,
+
+    #[error("Malformed {0} chunk")]
+    Malformed(&'static str),
+
+    #[error("Unsupported sample format {tag:#x} with {bits} bits")]
+    UnsupportedFormat { tag: u16, bits: u16 },
+}
+
+/// Samples as they're stored in the file, interleaved.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Samples {
+    U8(Vec<u8>),
+    I16(Vec<i16>),
+    /// Sign extended to 32 bits.
+    I24(Vec<i32>),
+    I32(Vec<i32>),
+    F32(Vec<f32>),
+    F64(Vec<f64>),
+}
+
+impl Samples {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::U8(s) => s.len(),
+            Self::I16(s) => s.len(),
+            Self::I24(s) | Self::I32(s) => s.len(),
+            Self::F32(s) => s.len(),
+            Self::F64(s) => s.len(),
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The `i`th sample scaled to -1..=1.
+    pub fn get(&self, i: usize) -> f64 {
+        const I24_MAX: f64 = ((1 << 23) - 1) as f64;
+
+        match self {
+            Self::U8(s) => (s[i] as f64 - 128.) / i8::MAX as f64,
+            Self::I16(s) => s[i] as f64 / i16::MAX as f64,
+            Self::I24(s) => s[i] as f64 / I24_MAX,
+            Self::I32(s) => s[i] as f64 / i32::MAX as f64,
+            Self::F32(s) => s[i] as f64,
+            Self::F64(s) => s[i],
+        }
+    }
+
+    pub fn to_f64(&self) -> Vec<f64> {
+        (0..self.len()).map(|i| self.get(i)).collect()
+    }
+
+    fn decode(format: SampleFormat, data: &[u8]) -> Self {
+        let chunks = |n| data.chunks_exact(n);
+
+        match format {
+            SampleFormat::Int(8) => Self::U8(data.to_vec()),
+            SampleFormat::Int(16) => Self::I16(
+                chunks(2)
+                    .map(|b| i16::from_le_bytes([b[0], b[1]]))
+                    .collect(),
+            ),
+            // shifting up then back down sign extends
+            SampleFormat::Int(24) => Self::I24(
+                chunks(3)
+                    .map(|b| i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8)
+                    .collect(),
+            ),
+            SampleFormat::Int(32) => Self::I32(
+                chunks(4)
+                    .map(|b| i32::from_le_bytes(b.try_into().unwrap()))
+                    .collect(),
+            ),
+
+            SampleFormat::Float(32) => Self::F32(
+                chunks(4)
+                    .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+                    .collect(),
+            ),
+            SampleFormat::Float(64) => Self::F64(
+                chunks(8)
+                    .map(|b| f64::from_le_bytes(b.try_into().unwrap()))
+                    .collect(),
+            ),
+
+            f => unreachable!("invalid sample format {f}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Wave {
+    pub desc: WaveDesc,
+    pub samples: Samples,
+}
+
+impl Wave {
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.desc.channels as usize
+    }
+
+    /// Sample `frame` of `channel`, scaled to -1..=1.
+    pub fn get(&self, frame: usize, channel: usize) -> f64 {
+        self.samples
+            .get(frame * self.desc.channels as usize + channel)
+    }
+}
+
+fn read_array<const N: usize>(r: &mut impl Read) -> std::io::Result<[u8; N]> {
+    let mut b = [0; N];
+    r.read_exact(&mut b)?;
+    Ok(b)
+}
+
+fn read_u32(r: &mut impl Read) -> std::io::Result<u32> {
+    Ok(u32::from_le_bytes(read_array(r)?))
+}
+
+fn skip(r: &mut impl Read, len: u64) -> std::io::Result<()> {
+    let skipped = std::io::copy(&mut r.take(len), &mut std::io::sink())?;
+
+    if skipped < len {
+        Err(std::io::ErrorKind::UnexpectedEof.into())
+    } else {
+        Ok(())
+    }
+}
+
+fn parse_fmt(body: &[u8]) -> Result<WaveDesc, WavError> {
+    if body.len() < 16 {
+        return Err(WavError::Malformed("fmt"));
+    }
+
+    let u16_at = |p: usize| u16::from_le_bytes([body[p], body[p + 1]]);
+    let u32_at = |p: usize| u32::from_le_bytes(body[p..p + 4].try_into().unwrap());
+
+    let mut tag = u16_at(0);
+    let channels = u16_at(2);
+    let samplerate = u32_at(4);
+    let block_align = u16_at(12);
+    let bits = u16_at(14);
+
+    if tag == WAVE_FORMAT_EXTENSIBLE {
+        // cbSize, valid bits, channel mask, then the subformat guid
+        if body.len() < 40 || body[26..40] != SUBFORMAT_GUID_TAIL {
+            return Err(WavError::Malformed("fmt"));
+        }
+
+        tag = u16_at(24);
+    }
+
+    let format = match tag {
+        WAVE_FORMAT_PCM => SampleFormat::new(bits, false),
+        WAVE_FORMAT_IEEE_FLOAT => SampleFormat::new(bits, true),
+
+        _ => None,
+    }
+    .ok_or(WavError::UnsupportedFormat { tag, bits })?;
+
+    let desc = WaveDesc::from_data(channels, samplerate, format);
+    if channels == 0 || desc.block_align() != block_align as usize {
+        return Err(WavError::Malformed("fmt"));
+    }
+
+    Ok(desc)
+}
+
+/// What the header of a wav file says, with the real sizes of RF64 files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub desc: WaveDesc,
+    /// Size of the RIFF/RF64 chunk, everything after its first 8 bytes.
+    pub riff_size: u64,
+    /// Length of the sample data in bytes.
+    pub data_size: u64,
+}
+
+/// Reads chunks up to the start of the sample data, `r` is left at the first sample.
+/// RF64 files are supported, unknown chunks are skipped.
+pub fn read_header(r: &mut impl Read) -> Result<Header, WavError> {
+    let id: [u8; 4] = read_array(r)?;
+    let mut riff_size = read_u32(r)? as u64;
+    let wave: [u8; 4] = read_array(r)?;
+
+    if !matches!(&id, b"RIFF" | b"RF64") || &wave != b"WAVE" {
+        return Err(WavError::NotWave);
+    }
+
+    let mut desc = None;
+    let mut ds64_data_size = None;
+
+    loop {
+        let chunk_id: [u8; 4] = match read_array(r) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                return Err(WavError::MissingData)
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let size = read_u32(r)? as u64;
+
+        match &chunk_id {
+            b"ds64" => {
+                if size < 24 {
+                    return Err(WavError::Malformed("ds64"));
+                }
+
+                let ds64_riff_size = u64::from_le_bytes(read_array(r)?);
+                if &id == b"RF64" && riff_size == u32::MAX as u64 {
+                    riff_size = ds64_riff_size;
+                }
+                ds64_data_size = Some(u64::from_le_bytes(read_array(r)?));
+
+                skip(r, size - 16)?;
+            }
+
+            b"fmt " => {
+                if size > MAX_FMT_SIZE {
+                    return Err(WavError::Malformed("fmt"));
+                }
+
+                let mut body = vec![0; size as usize];
+                r.read_exact(&mut body)?;
+
+                desc = Some(parse_fmt(&body)?);
+            }
+
+            b"data" => {
+                let desc = desc.ok_or(WavError::MissingFmt)?;
+
+                let data_size = match ds64_data_size {
+                    Some(s) if size == u32::MAX as u64 => s,
+                    _ => size,
+                };
+
+                return Ok(Header {
+                    desc,
+                    riff_size,
+                    data_size,
+                });
+            }
+
+            _ => skip(r, size)?,
+        }
+
+        // chunks are padded to even lengths
+        if size & 1 == 1 {
+            skip(r, 1)?;
+        }
+    }
+}
+
+/// Reads a whole wav file.
+///
+/// A data chunk that's cut short (for example a stream that was interrupted)
+/// is read up to the last whole frame.
+pub fn read(mut r: impl Read) -> Result<Wave, WavError> {
+    let Header {
+        desc, data_size, ..
+    } = read_header(&mut r)?;
+
+    let mut data = vec![];
+    r.take(data_size).read_to_end(&mut data)?;
+
+    let block_align = desc.block_align();
+    data.truncate(data.len() / block_align * block_align);
+
+    Ok(Wave {
+        samples: Samples::decode(desc.format, &data),
+        desc,
+    })
+}
+
+pub fn read_file(path: impl AsRef<Path>) -> Result<Wave, WavError> {
+    read(std::io::BufReader::new(std::fs::File::open(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{read, read_header, Samples, WavError};
+    use crate::wav::{SampleFormat, WaveDesc};
+
+    fn encode(format: SampleFormat, samples: &[f64]) -> Vec<u8> {
+        let mut data = vec![0; samples.len() * format.bytes()];
+        for (s, o) in samples.iter().zip(data.chunks_exact_mut(format.bytes())) {
+            format.encode(*s, o);
+        }
+
+        data
+    }
+
+    #[test]
+    fn round_trip() {
+        let samples = [0., 0.5, -0.5, 1., -1., 0.25];
+
+        for format in [
+            SampleFormat::Int(8),
+            SampleFormat::Int(16),
+            SampleFormat::Int(24),
+            SampleFormat::Int(32),
+            SampleFormat::Float(32),
+            SampleFormat::Float(64),
+        ] {
+            let desc = WaveDesc::from_data(2, 48000, format);
+
+            let mut file = vec![];
+            desc.write(&encode(format, &samples), &mut file).unwrap();
+
+            let wave = read(Cursor::new(file)).unwrap();
+            assert_eq!(wave.desc, desc);
+            assert_eq!(wave.frames(), 3);
+
+            let tolerance = 1. / (1u64 << (format.bits().min(24) - 1)) as f64;
+            for (i, s) in samples.iter().enumerate() {
+                let r = wave.get(i / 2, i % 2);
+                assert!((r - s).abs() <= tolerance, "{format}: {r} != {s}");
+            }
+        }
+    }
+
+    #[test]
+    fn extensible_and_unknown_chunks() {
+        let mut file = vec![];
+        file.extend_from_slice(b"RIFF");
+        file.extend_from_slice(&0u32.to_le_bytes());
+        file.extend_from_slice(b"WAVE");
+
+        // odd sized chunk, needs a pad byte
+        file.extend_from_slice(b"LIST");
+        file.extend_from_slice(&3u32.to_le_bytes());
+        file.extend_from_slice(&[1, 2, 3, 0]);
+
+        file.extend_from_slice(b"fmt ");
+        file.extend_from_slice(&40u32.to_le_bytes());
+        file.extend_from_slice(&0xFFFEu16.to_le_bytes());
+        file.extend_from_slice(&1u16.to_le_bytes());
+        file.extend_from_slice(&44100u32.to_le_bytes());
+        file.extend_from_slice(&(44100u32 * 4).to_le_bytes());
+        file.extend_from_slice(&4u16.to_le_bytes());
+        file.extend_from_slice(&32u16.to_le_bytes());
+        file.extend_from_slice(&22u16.to_le_bytes());
+        file.extend_from_slice(&32u16.to_le_bytes());
+        file.extend_from_slice(&4u32.to_le_bytes());
+        file.extend_from_slice(&3u16.to_le_bytes());
+        file.extend_from_slice(&super::SUBFORMAT_GUID_TAIL);
+
+        file.extend_from_slice(b"data");
+        file.extend_from_slice(&8u32.to_le_bytes());
+        file.extend_from_slice(&0.5f32.to_le_bytes());
+        file.extend_from_slice(&(-1f32).to_le_bytes());
+
+        let wave = read(Cursor::new(file)).unwrap();
+        assert_eq!(wave.desc.format(), SampleFormat::Float(32));
+        assert_eq!(wave.samples, Samples::F32(vec![0.5, -1.]));
+    }
+
+    #[test]
+    fn rf64_sizes_come_from_ds64() {
+        let data_size = u32::MAX as u64 + 10;
+
+        let mut file = vec![];
+        file.extend_from_slice(b"RF64");
+        file.extend_from_slice(&u32::MAX.to_le_bytes());
+        file.extend_from_slice(b"WAVE");
+
+        file.extend_from_slice(b"ds64");
+        file.extend_from_slice(&28u32.to_le_bytes());
+        file.extend_from_slice(&(data_size + 72).to_le_bytes());
+        file.extend_from_slice(&data_size.to_le_bytes());
+        file.extend_from_slice(&(data_size / 2).to_le_bytes());
+        file.extend_from_slice(&0u32.to_le_bytes());
+
+        let desc = WaveDesc::from_data(1, 48000, SampleFormat::Int(16));
+        file.extend_from_slice(b"fmt ");
+        file.extend_from_slice(&16u32.to_le_bytes());
+        file.extend_from_slice(&1u16.to_le_bytes());
+        file.extend_from_slice(&1u16.to_le_bytes());
+        file.extend_from_slice(&48000u32.to_le_bytes());
+        file.extend_from_slice(&96000u32.to_le_bytes());
+        file.extend_from_slice(&2u16.to_le_bytes());
+        file.extend_from_slice(&16u16.to_le_bytes());
+
+        file.extend_from_slice(b"data");
+        file.extend_from_slice(&u32::MAX.to_le_bytes());
+
+        let header = read_header(&mut &file[..]).unwrap();
+        assert_eq!(header.desc, desc);
+        assert_eq!(header.riff_size, data_size + 72);
+        assert_eq!(header.data_size, data_size);
+    }
+
+    #[test]
+    fn huge_fmt_chunks_are_rejected() {
+        let mut file = vec![];
+        file.extend_from_slice(b"RIFF");
+        file.extend_from_slice(&0u32.to_le_bytes());
+        file.extend_from_slice(b"WAVE");
+        file.extend_from_slice(b"fmt ");
+        file.extend_from_slice(&0xFFFF_FFF0u32.to_le_bytes());
+
+        let r = read_header(&mut &file[..]);
+        assert!(matches!(r, Err(WavError::Malformed("fmt"))));
+    }
+
+    #[test]
+    fn frames_too_big_for_the_header_are_rejected() {
+        // 2048 channels of 32 bit samples would be 65536 bytes a frame
+        for (channels, block_align) in [(2048u16, 0u16), (u16::MAX, u16::MAX), (2, 6)] {
+            let mut file = vec![];
+            file.extend_from_slice(b"RIFF");
+            file.extend_from_slice(&0u32.to_le_bytes());
+            file.extend_from_slice(b"WAVE");
+
+            file.extend_from_slice(b"fmt ");
+            file.extend_from_slice(&16u32.to_le_bytes());
+            file.extend_from_slice(&1u16.to_le_bytes());
+            file.extend_from_slice(&channels.to_le_bytes());
+            file.extend_from_slice(&48000u32.to_le_bytes());
+            file.extend_from_slice(&0u32.to_le_bytes());
+            file.extend_from_slice(&block_align.to_le_bytes());
+            file.extend_from_slice(&32u16.to_le_bytes());
+
+            file.extend_from_slice(b"data");
+            file.extend_from_slice(&4u32.to_le_bytes());
+            file.extend_from_slice(&[0; 4]);
+
+            let r = read(Cursor::new(file));
+            assert!(
+                matches!(r, Err(WavError::Malformed("fmt"))),
+                "{channels} channels: {r:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn not_a_wave() {
+        let r = read(Cursor::new(b"RIFF\0\0\0\0AVI LIST".to_vec()));
+        assert!(matches!(r, Err(WavError::NotWave)));
+    }
+}